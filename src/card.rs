@@ -1,83 +1,169 @@
+use crate::error::BlackjackError;
+use rand::Rng;
 use rand::seq::SliceRandom;
-use rand::thread_rng;
+use serde::{Deserialize, Serialize};
 use std::fmt::Display;
 
-#[derive(Clone, Debug)]
-pub enum Card {
-    Coeur(u8),
-    Pique(u8),
-    Carreau(u8),
-    Trefle(u8),
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Suit {
+    Coeur,
+    Pique,
+    Carreau,
+    Trefle,
 }
 
-impl Card {
-    pub fn unwrap(&self) -> &u8 {
+impl Suit {
+    pub const ALL: [Suit; 4] = [Suit::Coeur, Suit::Pique, Suit::Carreau, Suit::Trefle];
+}
+
+impl Display for Suit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let suit_str = match self {
+            Suit::Coeur => "Coeur",
+            Suit::Pique => "Pique",
+            Suit::Carreau => "Carreau",
+            Suit::Trefle => "Trefle",
+        };
+        write!(f, "{}", suit_str)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Rank {
+    Ace,
+    Two,
+    Three,
+    Four,
+    Five,
+    Six,
+    Seven,
+    Eight,
+    Nine,
+    Ten,
+    Jack,
+    Queen,
+    King,
+}
+
+impl Rank {
+    pub const ALL: [Rank; 13] = [
+        Rank::Ace,
+        Rank::Two,
+        Rank::Three,
+        Rank::Four,
+        Rank::Five,
+        Rank::Six,
+        Rank::Seven,
+        Rank::Eight,
+        Rank::Nine,
+        Rank::Ten,
+        Rank::Jack,
+        Rank::Queen,
+        Rank::King,
+    ];
+
+    // Value of the rank at blackjack, the ace counts 1 here (11 is decided by the hand)
+    pub fn value(&self) -> u8 {
         match self {
-            Card::Coeur(v) => v,
-            Card::Pique(v) => v,
-            Card::Carreau(v) => v,
-            Card::Trefle(v) => v,
+            Rank::Ace => 1,
+            Rank::Two => 2,
+            Rank::Three => 3,
+            Rank::Four => 4,
+            Rank::Five => 5,
+            Rank::Six => 6,
+            Rank::Seven => 7,
+            Rank::Eight => 8,
+            Rank::Nine => 9,
+            Rank::Ten | Rank::Jack | Rank::Queen | Rank::King => 10,
         }
     }
 }
 
-impl Display for Card {
+impl Display for Rank {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let color_str = match self {
-            Card::Coeur(_) => "Coeur",
-            Card::Pique(_) => "Pique",
-            Card::Carreau(_) => "Carreau",
-            Card::Trefle(_) => "Trefle",
-        };
-        write!(f, "{} {}", color_str, self.unwrap())
+        match self {
+            Rank::Ace => write!(f, "As"),
+            Rank::Jack => write!(f, "Valet"),
+            Rank::Queen => write!(f, "Dame"),
+            Rank::King => write!(f, "Roi"),
+            rank => write!(f, "{}", rank.value()),
+        }
     }
 }
 
-impl PartialEq for Card {
-    fn eq(&self, other: &Card) -> bool {
-        match (self, other) {
-            (Card::Coeur(a), Card::Coeur(b)) => a == b,
-            (Card::Pique(a), Card::Pique(b)) => a == b,
-            (Card::Carreau(a), Card::Carreau(b)) => a == b,
-            (Card::Trefle(a), Card::Trefle(b)) => a == b,
-            _ => false,
-        }
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Card {
+    pub rank: Rank,
+    pub suit: Suit,
+}
+
+impl Card {
+    pub fn new(rank: Rank, suit: Suit) -> Card {
+        Card { rank, suit }
+    }
+
+    // Blackjack value of the card (ace = 1)
+    pub fn value(&self) -> u8 {
+        self.rank.value()
     }
 }
 
-#[derive(Clone, Debug)]
+impl Display for Card {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.suit, self.rank)
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct PackOfCards {
     pack_cards: Vec<Card>,
 }
 
 impl PackOfCards {
-    pub fn init() -> PackOfCards {
-        let mut pack = PackOfCards {
-            pack_cards: Vec::new(),
-        };
-        for _ in 1..4 {
-            for n in 1..13 {
-                if n >= 10 {
-                    pack.pack_cards.push(Card::Coeur(10));
-                    pack.pack_cards.push(Card::Pique(10));
-                    pack.pack_cards.push(Card::Carreau(10));
-                    pack.pack_cards.push(Card::Trefle(10));
-                } else {
-                    pack.pack_cards.push(Card::Coeur(n));
-                    pack.pack_cards.push(Card::Pique(n));
-                    pack.pack_cards.push(Card::Carreau(n));
-                    pack.pack_cards.push(Card::Trefle(n));
-                }
+    // Standard 52 cards deck : 13 ranks in each of the 4 suits, not shuffled
+    pub fn standard_deck() -> PackOfCards {
+        let mut pack = PackOfCards::new();
+        for suit in Suit::ALL {
+            for rank in Rank::ALL {
+                pack.add_card(Card::new(rank, suit));
             }
         }
-        pack.shuffle();
         pack
     }
 
+    // Several standard decks put together, not shuffled
+    pub fn with_decks(decks: u8) -> PackOfCards {
+        let mut pack = PackOfCards::new();
+        for _ in 0..decks {
+            pack.pack_cards
+                .extend(PackOfCards::standard_deck().pack_cards);
+        }
+        pack
+    }
+
+    // Cards of the given ranks, for the tests of the hands
+    #[cfg(test)]
+    pub fn from_ranks(ranks: &[Rank]) -> PackOfCards {
+        PackOfCards {
+            pack_cards: ranks
+                .iter()
+                .map(|&rank| Card::new(rank, Suit::Pique))
+                .collect(),
+        }
+    }
+
     pub fn iterator(&self) -> &Vec<Card> {
         &self.pack_cards
     }
 
+    pub fn len(&self) -> usize {
+        self.pack_cards.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pack_cards.is_empty()
+    }
+
     pub fn get_card(&self, index: usize) -> Option<&Card> {
         self.pack_cards.get(index)
     }
@@ -92,32 +178,167 @@ impl PackOfCards {
         self.pack_cards.push(card);
     }
 
-    pub fn shuffle(&mut self) {
-        let mut rng = thread_rng();
-        self.pack_cards.shuffle(&mut rng);
+    // Shuffle with a given generator, the same seed gives the same order
+    pub fn shuffle<R: Rng + ?Sized>(&mut self, rng: &mut R) {
+        self.pack_cards.shuffle(rng);
     }
 
-    pub fn pick(&mut self) -> Result<Card, &str> {
+    // Take out one card equal to `card`, false when there is none
+    pub fn remove(&mut self, card: &Card) -> bool {
+        match self.pack_cards.iter().position(|c| c == card) {
+            Some(index) => {
+                self.pack_cards.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn pick(&mut self) -> Result<Card, BlackjackError> {
         match self.pack_cards.pop() {
             Some(card) => Ok(card),
-            None => Err("No more cards"),
+            None => Err(BlackjackError::EmptyShoe),
         }
     }
 
-    pub fn sum(&self) -> u8 {
-        let mut total = 0;
+    pub fn value(&self) -> HandValue {
+        let mut value = HandValue {
+            hard: 0,
+            has_ace: false,
+            cards: self.pack_cards.len(),
+        };
         for card in &self.pack_cards {
-            match card.unwrap() {
-                1 => {
-                    if total + 11 > 21 {
-                        total += 1;
-                    } else {
-                        total += 11;
-                    }
-                }
-                value => total += value,
+            value.hard = value.hard.saturating_add(card.value());
+            value.has_ace |= card.rank == Rank::Ace;
+        }
+        value
+    }
+}
+
+// Every ace counts 1 in the hard total, one of them counts 11 when the hand stays under 22
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HandValue {
+    hard: u8,
+    has_ace: bool,
+    cards: usize,
+}
+
+impl HandValue {
+    // Value of a hand known only by its hard total, used by the solver
+    pub fn new(hard: u8, has_ace: bool, cards: usize) -> HandValue {
+        HandValue {
+            hard,
+            has_ace,
+            cards,
+        }
+    }
+
+    pub fn hard_total(&self) -> u8 {
+        self.hard
+    }
+
+    // Total with one ace counted 11, equal to the hard total when that would bust
+    pub fn soft_total(&self) -> u8 {
+        if self.is_soft() {
+            self.hard + 10
+        } else {
+            self.hard
+        }
+    }
+
+    // Best total of the hand
+    pub fn total(&self) -> u8 {
+        self.soft_total()
+    }
+
+    pub fn is_soft(&self) -> bool {
+        self.has_ace && self.hard <= 11
+    }
+
+    pub fn is_bust(&self) -> bool {
+        self.hard > 21
+    }
+
+    // 21 with the first two cards
+    pub fn is_blackjack(&self) -> bool {
+        self.cards == 2 && self.total() == 21
+    }
+}
+
+impl Display for HandValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_blackjack() {
+            write!(f, "blackjack")
+        } else if self.is_soft() {
+            write!(f, "soft {}", self.total())
+        } else {
+            write!(f, "{}", self.total())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn standard_deck_has_every_card_once() {
+        let deck = PackOfCards::standard_deck();
+        assert_eq!(deck.len(), 52);
+        for suit in Suit::ALL {
+            for rank in Rank::ALL {
+                let card = Card::new(rank, suit);
+                assert_eq!(deck.iterator().iter().filter(|c| **c == card).count(), 1);
             }
         }
-        total
+        // the ten and the three figures are worth ten
+        let tens = deck.iterator().iter().filter(|c| c.value() == 10).count();
+        assert_eq!(tens, 16);
+        assert_eq!(PackOfCards::with_decks(6).len(), 6 * 52);
+    }
+
+    fn hand(ranks: &[Rank]) -> HandValue {
+        PackOfCards::from_ranks(ranks).value()
+    }
+
+    #[test]
+    fn aces_count_eleven_only_when_the_hand_stays_under_22() {
+        // the ace can no longer count 11 after the five
+        let value = hand(&[Rank::Ace, Rank::Nine, Rank::Five]);
+        assert_eq!((value.hard_total(), value.total()), (15, 15));
+        assert!(!value.is_soft());
+        assert_eq!(value.to_string(), "15");
+
+        let value = hand(&[Rank::Ace, Rank::Six]);
+        assert_eq!((value.hard_total(), value.total()), (7, 17));
+        assert!(value.is_soft());
+        assert_eq!(value.to_string(), "soft 17");
+
+        // only one of two aces counts 11
+        let value = hand(&[Rank::Ace, Rank::Ace]);
+        assert_eq!((value.hard_total(), value.total()), (2, 12));
+        assert!(value.is_soft() && !value.is_bust());
+
+        assert!(hand(&[Rank::King, Rank::Queen, Rank::Two]).is_bust());
+        // the total stops at 255 however long the hand
+        let value = hand(&[Rank::King; 30]);
+        assert_eq!(value.hard_total(), u8::MAX);
+        assert!(value.is_bust());
+    }
+
+    #[test]
+    fn only_two_cards_make_a_blackjack() {
+        let value = hand(&[Rank::Ace, Rank::King]);
+        assert!(value.is_blackjack());
+        assert_eq!(value.to_string(), "blackjack");
+
+        for ranks in [
+            [Rank::Seven, Rank::Seven, Rank::Seven],
+            [Rank::Ace, Rank::Five, Rank::Five],
+        ] {
+            let value = hand(&ranks);
+            assert_eq!(value.total(), 21);
+            assert!(!value.is_blackjack());
+        }
     }
 }
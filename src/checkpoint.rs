@@ -0,0 +1,127 @@
+use crate::card::PackOfCards;
+use crate::error::BlackjackError;
+use crate::rules::RuleSet;
+use crate::shoe::{DEFAULT_BURN_CARDS, DEFAULT_PENETRATION, Shoe};
+use crate::training::{QTable, State};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::Instant;
+
+pub const CHECK_EVERY: u64 = 1_000; // Games of each thread between two looks at the checkpoint clock
+pub const DEFAULT_KEEP: usize = 3; // Checkpoints kept on disk, the oldest ones are removed
+
+// Where a training thread stands : its next game, its generator and its shoe
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ThreadState {
+    pub episode: u64,
+    pub rng: ChaCha12Rng,
+    pub shoe: Shoe,
+    pub discard: PackOfCards,
+}
+
+impl ThreadState {
+    // Start of a thread whose generator is seeded with `seed`
+    pub fn new(rules: &RuleSet, seed: u64) -> ThreadState {
+        let mut rng = ChaCha12Rng::seed_from_u64(seed);
+        let shoe = Shoe::seeded(
+            rules.decks,
+            DEFAULT_PENETRATION,
+            DEFAULT_BURN_CARDS,
+            rng.r#gen(),
+        );
+        ThreadState {
+            episode: 0,
+            rng,
+            shoe,
+            discard: PackOfCards::new(),
+        }
+    }
+}
+
+// State of a run saved with its table in a checkpoint, the table keeps its visits and hyperparameters
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RunState {
+    pub episodes: u64,   // games asked to each thread
+    pub checkpoint: u64, // number of this checkpoint in the run
+    pub threads: Vec<ThreadState>,
+    pub learner: Vec<(State, Vec<f32>)>, // values the learner keeps besides the table
+    #[serde(default)]
+    pub learner_visits: Vec<(State, Vec<u64>)>, // updates of each of these values
+}
+
+// When and where the checkpoints of a run are written
+#[derive(Clone, Debug, PartialEq)]
+pub struct CheckpointOptions {
+    pub every: Option<u64>,   // games of all the threads together
+    pub minutes: Option<f64>, // time between two checkpoints
+    pub keep: usize,
+    pub path: String, // table file the checkpoints are named after
+}
+
+impl CheckpointOptions {
+    pub fn enabled(&self) -> bool {
+        self.every.is_some() || self.minutes.is_some()
+    }
+
+    // `qtable.json` gives `qtable.checkpoint-3.json`
+    pub fn file(&self, index: u64) -> String {
+        let stem = self.path.strip_suffix(".json").unwrap_or(&self.path);
+        format!("{}.checkpoint-{}.json", stem, index)
+    }
+}
+
+// Last checkpoint written : its number, the games of the table and the time then
+struct Clock {
+    index: u64,
+    games: u64,
+    time: Instant,
+}
+
+pub struct Checkpointer {
+    options: CheckpointOptions,
+    clock: Mutex<Clock>,
+}
+
+impl Checkpointer {
+    // `index` is the number of the last checkpoint of the run, 0 for a new run
+    pub fn new(options: CheckpointOptions, games: u64, index: u64) -> Checkpointer {
+        Checkpointer {
+            options,
+            clock: Mutex::new(Clock {
+                index,
+                games,
+                time: Instant::now(),
+            }),
+        }
+    }
+
+    pub fn due(&self, games: u64) -> bool {
+        let clock = self.clock.lock().unwrap();
+        self.options
+            .every
+            .is_some_and(|every| games - clock.games >= every)
+            || self
+                .options
+                .minutes
+                .is_some_and(|minutes| clock.time.elapsed().as_secs_f64() >= minutes * 60.0)
+    }
+
+    // Write the next checkpoint and remove the ones that are too old, returns its path
+    pub fn save(&self, table: &QTable, mut run: RunState) -> Result<String, BlackjackError> {
+        let mut clock = self.clock.lock().unwrap();
+        run.checkpoint = clock.index + 1;
+        let path = self.options.file(run.checkpoint);
+        table.save_checkpoint(&path, run)?;
+
+        clock.index += 1;
+        clock.games = table.games();
+        clock.time = Instant::now();
+        if clock.index > self.options.keep as u64 {
+            let old = self.options.file(clock.index - self.options.keep as u64);
+            let _ = std::fs::remove_file(old); // already removed by hand
+        }
+        Ok(path)
+    }
+}
@@ -0,0 +1,1093 @@
+use crate::card::{Card, Rank, Suit};
+use crate::checkpoint::{CheckpointOptions, DEFAULT_KEEP};
+use crate::config::{DEFAULT_EPISODES, DEFAULT_THREADS, TrainingConfig};
+use crate::counting::{SYSTEMS, system_from_name};
+use crate::error::BlackjackError;
+use crate::rules::{BlackjackPayout, DoubleRule, RuleSet, Surrender};
+use crate::schedule::{Exploration, LearningRate};
+use crate::session::{BetRamp, Session};
+use crate::table::MAX_SEATS;
+use crate::training::StateEncoder;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+pub const DEFAULT_HANDS: u64 = 1_000_000; // Hands played by the evaluation of a policy
+pub const DEFAULT_TABLE: &str = "qtable.json";
+pub const DEFAULT_SESSION_HANDS: u64 = 100_000; // Hands of a simulated session
+pub const DEFAULT_HORIZON: u64 = 10_000; // Hands of each session of the risk of ruin simulation
+pub const DEFAULT_TRAJECTORIES: u64 = 1_000;
+pub const DEFAULT_ROUNDS: u64 = 100_000; // Rounds of a table without human seat
+
+pub struct TrainOptions {
+    pub config: TrainingConfig,
+    pub output: String,
+    pub checkpoints: CheckpointOptions,
+    pub resume: Option<String>, // checkpoint the run goes on from
+}
+
+pub struct PlayOptions {
+    pub rules: RuleSet,
+    pub hint: Option<String>, // policy giving advice, see `POLICIES`
+    pub bet: f32,
+    pub bankroll: Option<f32>,
+}
+
+pub struct EvalOptions {
+    pub policy: String,
+    pub hands: u64,
+    pub seed: u64,
+    pub chart: Option<String>, // file of the basic strategy chart, solved and written when missing
+    pub rules: RuleSet,        // only for the built-in policies, a table keeps its own rules
+}
+
+pub struct AdviseOptions {
+    pub policy: String,
+    pub cards: Vec<Card>,
+    pub up_card: Card,
+    pub rules: RuleSet,
+}
+
+pub struct SessionOptions {
+    pub policy: String,
+    pub session: Session,
+    pub rules: RuleSet, // only for the built-in policies, a table keeps its own rules
+}
+
+pub struct RiskOptions {
+    pub policy: String,
+    pub session: Session, // bets and length of the simulated sessions
+    pub hands: u64,       // flat bet hands giving the results per true count
+    pub kelly_fraction: f64,
+    pub trajectories: u64,
+    pub rules: RuleSet,
+}
+
+pub struct TableOptions {
+    pub seats: Vec<String>, // policy of each seat from the first one dealt, human or see `POLICIES`
+    pub rounds: u64,        // only when no seat is human
+    pub bet: f32,
+    pub seed: Option<u64>,
+    pub rules: RuleSet, // also for the tables, which are played on these rules
+}
+
+pub struct ExportOptions {
+    pub rules: RuleSet,
+    pub json: bool,
+    pub output: Option<String>,
+    pub compare: Option<String>, // table compared with the chart
+}
+
+pub enum Command {
+    Train(TrainOptions),
+    Play(PlayOptions),
+    Eval(EvalOptions),
+    Advise(AdviseOptions),
+    Export(ExportOptions),
+    Session(SessionOptions),
+    Risk(RiskOptions),
+    Table(TableOptions),
+    Bench { max_threads: Option<usize> },
+    Help(Option<String>),
+}
+
+const COMMANDS: [&str; 9] = [
+    "train", "play", "eval", "advise", "export", "session", "risk", "table", "bench",
+];
+
+// Policies that can be named instead of a table file
+pub const POLICIES: [&str; 4] = ["basic", "mimic-dealer", "never-bust", "random"];
+
+const RULE_VALUES: [&str; 5] = [
+    "--decks",
+    "--payout",
+    "--double",
+    "--surrender",
+    "--max-splits",
+];
+const SESSION_VALUES: [&str; 7] = [
+    "--bankroll",
+    "--unit",
+    "--ramp",
+    "--table-min",
+    "--table-max",
+    "--count",
+    "--seed",
+];
+// Each switch comes with its opposite, to change back a rule of a configuration file
+const RULE_SWITCHES: [&str; 14] = [
+    "--h17",
+    "--s17",
+    "--no-das",
+    "--das",
+    "--resplit-aces",
+    "--no-resplit-aces",
+    "--hit-split-aces",
+    "--no-hit-split-aces",
+    "--no-insurance",
+    "--insurance",
+    "--no-peek",
+    "--peek",
+    "--enhc",
+    "--hole-card",
+];
+
+const RULES_HELP: &str = "Table rules:
+  --decks N             decks in the shoe, 1 to 8 (default 6)
+  --h17                 dealer hits soft 17
+  --payout P            blackjack payout: 3:2, 6:5 or 1:1 (default 3:2)
+  --double D            doubling allowed on: any, 9-11 or 10-11 (default any)
+  --no-das              no double after split
+  --max-splits N        splits allowed in one round, 0 to 7 (default 3)
+  --resplit-aces        split again an ace dealt on split aces
+  --hit-split-aces      draw on split aces
+  --surrender S         none, late or early (default none)
+  --no-insurance        no insurance nor even money
+  --no-peek             the dealer does not check for blackjack
+  --enhc                no hole card (European game)
+The switches --s17, --das, --no-resplit-aces, --no-hit-split-aces, --insurance,
+--peek and --hole-card turn back a rule set by the configuration file.";
+
+pub fn usage(command: Option<&str>) -> String {
+    match command {
+        Some("train") => format!(
+            "Usage: black_jack_ia train [options]
+
+Trains a Q-table and saves it.
+  --config PATH         JSON file of the configuration (TOML when it ends with .toml),
+                        the options below override it
+  --episodes N          games played by each thread (default {})
+  --threads N           training threads (default {})
+  --alpha A             constant learning rate, in (0, 1] (default 0.1)
+  --learning-rate R     constant:A, visits (1/N of the state and action)
+                        or exponential:START:DECAY:MIN
+  --gamma G             discount factor, in [0, 1] (default 1)
+  --epsilon S           epsilon-greedy exploration: constant:E, linear:START:END:PART,
+                        exponential:START:END:DECAY, inverse-time:START:K or visits:C
+                        (default linear:1:0.02:0.05)
+  --boltzmann T0:T1     softmax exploration, temperature from T0 to T1
+  --initial-value V     value of the actions never tried, optimistic above 0 (default 0)
+  --learner L           q-learning, sarsa, expected-sarsa, monte-carlo-first-visit,
+                        monte-carlo-every-visit or double-q-learning (default q-learning)
+  --encoder E           state encoder: classic, composition or count (default classic)
+  --seed N              master seed of the random generators (default random)
+  --output PATH         file of the table (default {})
+
+Checkpoints, written next to the table as NAME.checkpoint-N.json:
+  --checkpoint-every N  every N games of all the threads together
+  --checkpoint-minutes M
+                        every M minutes
+  --checkpoint-keep K   checkpoints kept, the oldest ones are removed (default {})
+  --resume PATH         go on with the run of a checkpoint, with its configuration
+
+{}",
+            DEFAULT_EPISODES, DEFAULT_THREADS, DEFAULT_TABLE, DEFAULT_KEEP, RULES_HELP
+        ),
+        Some("play") => format!(
+            "Usage: black_jack_ia play [options]
+
+Plays against the dealer in the terminal.
+  --hint POLICY         advice before every decision: a table file or {}
+  --bet B               bet of each hand (default 1)
+  --bankroll B          money available, the game stops when it is gone
+
+{}",
+            POLICIES.join(", "),
+            RULES_HELP
+        ),
+        Some("eval") => format!(
+            "Usage: black_jack_ia eval [POLICY] [options]
+
+Plays a policy without exploration and prints its results.
+POLICY is a table file (default {}) or one of {}.
+  --hands N             hands played (default {})
+  --seed N              seed of the shoe (default 0)
+  --chart FILE          basic strategy chart read from FILE, solved and written
+                        there when FILE is missing or holds other rules
+
+The rules options apply to the built-in policies, a table is evaluated on its own rules.
+{}",
+            DEFAULT_TABLE,
+            POLICIES.join(", "),
+            DEFAULT_HANDS,
+            RULES_HELP
+        ),
+        Some("advise") => format!(
+            "Usage: black_jack_ia advise [POLICY] --cards C,C[,C...] --up C [options]
+
+Prints the best action for a hand. Cards are A, 2 to 10, J, Q or K.
+POLICY is a table file (default {}) or one of {}.
+The values of the actions are printed for a table or basic, the other policies only
+give their action.
+  --cards LIST          cards of the player, separated by commas
+  --up C                up card of the dealer
+
+{}",
+            DEFAULT_TABLE,
+            POLICIES.join(", "),
+            RULES_HELP
+        ),
+        Some("export") => format!(
+            "Usage: black_jack_ia export [options]
+
+Computes the basic strategy chart of the rules (see solver.rs).
+  --json                write the chart and its values as JSON instead of text
+  --output PATH         write to a file instead of the terminal
+  --compare PATH        count the states of a table that play the chart action
+
+{}",
+            RULES_HELP
+        ),
+        Some("session") => format!(
+            "Usage: black_jack_ia session [POLICY] [options]
+
+Plays a session with a bankroll, the bet of each hand follows the true count.
+POLICY is a table file (default {}) or one of {}.
+  --hands N             hands played at most (default {})
+  --bankroll B          money at the start, the session stops when the table minimum
+                        cannot be paid (default 10000)
+  --unit U              bet of one unit (default 10)
+  --ramp LIST           units for each true count, TC:UNITS separated by commas,
+                        1 unit below the first one (default {})
+  --table-min M         smallest bet of the table (default 10)
+  --table-max M         largest bet of the table (default 500)
+  --count SYSTEM        {} (default hi-lo)
+  --seed N              seed of the shoe (default 0)
+
+{}",
+            DEFAULT_TABLE,
+            POLICIES.join(", "),
+            DEFAULT_SESSION_HANDS,
+            BetRamp::default(),
+            SYSTEMS.map(|system| system.name).join(", "),
+            RULES_HELP
+        ),
+        Some("risk") => format!(
+            "Usage: black_jack_ia risk [POLICY] [options]
+
+Kelly bets per true count and risk of ruin of a bet ramp, computed from the results
+per true count and checked by simulated sessions.
+POLICY is a table file (default {}) or one of {}.
+  --hands N             flat bet hands giving the results per true count (default {})
+  --kelly-fraction F    part of the Kelly bet played, 0.5 for half Kelly (default 0.5)
+  --trajectories N      sessions simulated for the risk of ruin (default {})
+  --horizon N           hands of each simulated session (default {})
+  --bankroll, --unit, --ramp, --table-min, --table-max, --count, --seed
+                        as for the session command
+
+{}",
+            DEFAULT_TABLE,
+            POLICIES.join(", "),
+            DEFAULT_HANDS,
+            DEFAULT_TRAJECTORIES,
+            DEFAULT_HORIZON,
+            RULES_HELP
+        ),
+        Some("table") => format!(
+            "Usage: black_jack_ia table --seats LIST [options]
+
+Plays a table of 1 to {} seats dealt from one shoe, the dealer plays once for all of them.
+With a human seat the rounds are played in the terminal, otherwise the results of each
+seat are printed.
+  --seats LIST          policy of each seat, separated by commas from the first seat
+                        dealt: human, a table file or one of {} (default human)
+  --rounds N            rounds played without human seat (default {})
+  --bet B               bet of each seat (default 1)
+  --seed N              seed of the shoe (default: random)
+
+{}",
+            MAX_SEATS,
+            POLICIES.join(", "),
+            DEFAULT_ROUNDS,
+            RULES_HELP
+        ),
+        Some("bench") => "Usage: black_jack_ia bench [MAX_THREADS]
+
+Measures the training speed from 1 thread to MAX_THREADS (default: number of cores)."
+            .to_string(),
+        _ => format!(
+            "Usage: black_jack_ia <command> [options]
+
+Commands:
+  train     train a Q-table (default command)
+  play      play in the terminal, with optional advice
+  eval      evaluate a table or a built-in policy
+  advise    best action for a hand
+  export    basic strategy chart
+  session   bankroll session with a bet ramp on the true count
+  risk      Kelly bets and risk of ruin of a bet ramp
+  table     several seats sharing one shoe
+  bench     training speed for several numbers of threads
+  help      this message, `help <command>` for the options of a command
+
+Built-in policies: {}",
+            POLICIES.join(", ")
+        ),
+    }
+}
+
+fn invalid(message: String) -> BlackjackError {
+    BlackjackError::InvalidArgument(message)
+}
+
+// Options of one command line : `--name value`, `--name=value`, switches and positional arguments
+struct Options {
+    values: HashMap<String, String>,
+    switches: HashSet<String>,
+    positionals: Vec<String>,
+}
+
+impl Options {
+    fn read(
+        args: &[String],
+        with_value: &[&str],
+        switches: &[&str],
+        max_positionals: usize,
+    ) -> Result<Options, BlackjackError> {
+        let mut options = Options {
+            values: HashMap::new(),
+            switches: HashSet::new(),
+            positionals: Vec::new(),
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if !arg.starts_with("--") {
+                if options.positionals.len() == max_positionals {
+                    return Err(invalid(format!("unexpected argument '{}'", arg)));
+                }
+                options.positionals.push(arg.clone());
+                continue;
+            }
+            let (name, inline) = match arg.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (arg.as_str(), None),
+            };
+            if with_value.contains(&name) {
+                let value = match inline {
+                    Some(value) => value,
+                    None => args
+                        .next()
+                        .cloned()
+                        .ok_or_else(|| invalid(format!("option {} needs a value", name)))?,
+                };
+                if options.values.insert(name.to_string(), value).is_some() {
+                    return Err(invalid(format!("option {} given twice", name)));
+                }
+            } else if switches.contains(&name) && inline.is_none() {
+                options.switches.insert(name.to_string());
+            } else {
+                return Err(invalid(format!("unknown option '{}'", arg)));
+            }
+        }
+        Ok(options)
+    }
+
+    fn get<T: FromStr>(&self, name: &str, default: T) -> Result<T, BlackjackError> {
+        match self.values.get(name) {
+            Some(value) => value
+                .parse()
+                .map_err(|_| invalid(format!("invalid value '{}' for {}", value, name))),
+            None => Ok(default),
+        }
+    }
+
+    fn get_optional<T: FromStr>(&self, name: &str) -> Result<Option<T>, BlackjackError> {
+        self.values
+            .get(name)
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|_| invalid(format!("invalid value '{}' for {}", value, name)))
+            })
+            .transpose()
+    }
+
+    fn has(&self, name: &str) -> bool {
+        self.switches.contains(name)
+    }
+
+    // Rule turned on or off by a switch, else the one of the base rules
+    fn switch(&self, on: &str, off: &str, base: bool) -> Result<bool, BlackjackError> {
+        match (self.has(on), self.has(off)) {
+            (true, true) => Err(invalid(format!(
+                "{} and {} cannot be given together",
+                on, off
+            ))),
+            (true, false) => Ok(true),
+            (false, true) => Ok(false),
+            (false, false) => Ok(base),
+        }
+    }
+
+    fn rules(&self) -> Result<RuleSet, BlackjackError> {
+        self.rules_over(RuleSet::default())
+    }
+
+    // Rules of `base` changed by the rules options
+    fn rules_over(&self, base: RuleSet) -> Result<RuleSet, BlackjackError> {
+        let decks: u8 = self.get("--decks", base.decks)?;
+        if !(1..=8).contains(&decks) {
+            return Err(invalid(format!(
+                "--decks must be between 1 and 8, not {}",
+                decks
+            )));
+        }
+        let max_splits: u8 = self.get("--max-splits", base.max_splits)?;
+        if max_splits > 7 {
+            return Err(invalid(format!(
+                "--max-splits must be between 0 and 7, not {}",
+                max_splits
+            )));
+        }
+        let blackjack_payout = match self.values.get("--payout").map(String::as_str) {
+            None => base.blackjack_payout,
+            Some("3:2") => BlackjackPayout::ThreeToTwo,
+            Some("6:5") => BlackjackPayout::SixToFive,
+            Some("1:1") => BlackjackPayout::EvenMoney,
+            Some(other) => return Err(invalid(format!("unknown payout '{}'", other))),
+        };
+        let double = match self.values.get("--double").map(String::as_str) {
+            None => base.double,
+            Some("any") => DoubleRule::AnyTwo,
+            Some("9-11") => DoubleRule::NineToEleven,
+            Some("10-11") => DoubleRule::TenToEleven,
+            Some(other) => return Err(invalid(format!("unknown double rule '{}'", other))),
+        };
+        let surrender = match self.values.get("--surrender").map(String::as_str) {
+            None => base.surrender,
+            Some("none") => Surrender::None,
+            Some("late") => Surrender::Late,
+            Some("early") => Surrender::Early,
+            Some(other) => return Err(invalid(format!("unknown surrender rule '{}'", other))),
+        };
+        Ok(RuleSet {
+            decks,
+            dealer_hits_soft_17: self.switch("--h17", "--s17", base.dealer_hits_soft_17)?,
+            blackjack_payout,
+            double,
+            double_after_split: self.switch("--das", "--no-das", base.double_after_split)?,
+            max_splits,
+            resplit_aces: self.switch("--resplit-aces", "--no-resplit-aces", base.resplit_aces)?,
+            hit_split_aces: self.switch(
+                "--hit-split-aces",
+                "--no-hit-split-aces",
+                base.hit_split_aces,
+            )?,
+            surrender,
+            insurance: self.switch("--insurance", "--no-insurance", base.insurance)?,
+            dealer_peek: self.switch("--peek", "--no-peek", base.dealer_peek)?,
+            no_hole_card: self.switch("--enhc", "--hole-card", base.no_hole_card)?,
+        })
+    }
+
+    // Table file or built-in policy given as first positional argument
+    fn policy(&self) -> String {
+        self.positionals
+            .first()
+            .cloned()
+            .unwrap_or_else(|| DEFAULT_TABLE.to_string())
+    }
+
+    // Money and bets of a session of `hands` hands, see `with_session`
+    fn session(&self, hands: u64) -> Result<Session, BlackjackError> {
+        let count = self.get("--count", "hi-lo".to_string())?;
+        let session = Session {
+            hands,
+            bankroll: self.get("--bankroll", 10_000.0)?,
+            unit: self.get("--unit", 10.0)?,
+            ramp: match self.values.get("--ramp") {
+                Some(ramp) => ramp.parse().map_err(invalid)?,
+                None => BetRamp::default(),
+            },
+            table_min: self.get("--table-min", 10.0)?,
+            table_max: self.get("--table-max", 500.0)?,
+            system: system_from_name(&count)
+                .ok_or_else(|| invalid(format!("unknown counting system '{}'", count)))?,
+            seed: self.get("--seed", 0)?,
+        };
+        let money = [
+            session.bankroll,
+            session.unit,
+            session.table_min,
+            session.table_max,
+        ];
+        if session.hands == 0
+            || !money.iter().all(|money| money.is_finite())
+            || session.unit <= 0.0
+            || session.table_min <= 0.0
+            || session.table_max < session.table_min
+            || session.bankroll < session.table_min
+        {
+            return Err(invalid(
+                "the hands and --unit must be positive, the amounts finite, the table limits \
+                 in order and --bankroll at least --table-min"
+                    .to_string(),
+            ));
+        }
+        Ok(session)
+    }
+}
+
+fn with_rules<'a>(options: &[&'a str]) -> Vec<&'a str> {
+    options.iter().copied().chain(RULE_VALUES).collect()
+}
+
+fn with_session<'a>(options: &[&'a str]) -> Vec<&'a str> {
+    [options, &SESSION_VALUES].concat()
+}
+
+fn rule_switches<'a>(switches: &[&'a str]) -> Vec<&'a str> {
+    switches.iter().copied().chain(RULE_SWITCHES).collect()
+}
+
+fn parse_card(s: &str) -> Result<Card, BlackjackError> {
+    let rank = match s.to_uppercase().as_str() {
+        "A" | "1" => Rank::Ace,
+        "J" => Rank::Jack,
+        "Q" => Rank::Queen,
+        "K" => Rank::King,
+        "T" => Rank::Ten,
+        number => match number.parse::<usize>() {
+            Ok(value @ 2..=10) => Rank::ALL[value - 1],
+            _ => return Err(invalid(format!("unknown card '{}'", s))),
+        },
+    };
+    Ok(Card::new(rank, Suit::Coeur))
+}
+
+pub fn parse(args: &[String]) -> Result<Command, BlackjackError> {
+    let Some(command) = args.first() else {
+        // No command : training with the default options
+        return parse(&["train".to_string()]);
+    };
+    let rest = &args[1..];
+    if command == "help" || command == "--help" || command == "-h" {
+        return Ok(Command::Help(rest.first().cloned()));
+    }
+    if !COMMANDS.contains(&command.as_str()) {
+        return Err(invalid(format!("unknown command '{}'", command)));
+    }
+    if rest.iter().any(|arg| arg == "--help" || arg == "-h") {
+        return Ok(Command::Help(Some(command.clone())));
+    }
+
+    match command.as_str() {
+        "train" => {
+            let options = Options::read(
+                rest,
+                &with_rules(&[
+                    "--config",
+                    "--episodes",
+                    "--threads",
+                    "--alpha",
+                    "--learning-rate",
+                    "--gamma",
+                    "--epsilon",
+                    "--boltzmann",
+                    "--initial-value",
+                    "--learner",
+                    "--encoder",
+                    "--seed",
+                    "--output",
+                    "--checkpoint-every",
+                    "--checkpoint-minutes",
+                    "--checkpoint-keep",
+                    "--resume",
+                ]),
+                &RULE_SWITCHES,
+                0,
+            )?;
+            let output = options.get("--output", DEFAULT_TABLE.to_string())?;
+            let checkpoints = CheckpointOptions {
+                every: options.get_optional("--checkpoint-every")?,
+                minutes: options.get_optional("--checkpoint-minutes")?,
+                keep: options.get("--checkpoint-keep", DEFAULT_KEEP)?,
+                path: output.clone(),
+            };
+            if checkpoints.every == Some(0)
+                || checkpoints
+                    .minutes
+                    .is_some_and(|minutes| !(minutes.is_finite() && minutes > 0.0))
+                || checkpoints.keep == 0
+            {
+                return Err(invalid(
+                    "--checkpoint-every, --checkpoint-minutes and --checkpoint-keep must be positive"
+                        .to_string(),
+                ));
+            }
+            // A resumed run keeps the configuration written in its checkpoint
+            let resume = options.values.get("--resume").cloned();
+            if resume.is_some()
+                && (!options.switches.is_empty()
+                    || options.values.keys().any(|name| {
+                        name != "--resume"
+                            && name != "--output"
+                            && !name.starts_with("--checkpoint-")
+                    }))
+            {
+                return Err(invalid(
+                    "only --output and the checkpoint options can be given with --resume"
+                        .to_string(),
+                ));
+            }
+            // The file gives the base of the configuration, the options change it
+            let mut config = match options.values.get("--config") {
+                Some(path) => TrainingConfig::load(path)?,
+                None => TrainingConfig::default(),
+            };
+            config.episodes = options.get("--episodes", config.episodes)?;
+            config.threads = options.get("--threads", config.threads)?;
+            if options.values.contains_key("--alpha")
+                && options.values.contains_key("--learning-rate")
+            {
+                return Err(invalid(
+                    "--alpha and --learning-rate cannot be given together".to_string(),
+                ));
+            }
+            if let Some(alpha) = options.get_optional("--alpha")? {
+                config.learning_rate = LearningRate::Constant(alpha);
+            }
+            if let Some(rate) = options.values.get("--learning-rate") {
+                config.learning_rate = rate.parse().map_err(invalid)?;
+            }
+            config.gamma = options.get("--gamma", config.gamma)?;
+            config.exploration = match (
+                options.values.get("--epsilon"),
+                options.values.get("--boltzmann"),
+            ) {
+                (Some(_), Some(_)) => {
+                    return Err(invalid(
+                        "--epsilon and --boltzmann cannot be given together".to_string(),
+                    ));
+                }
+                (Some(schedule), None) => {
+                    Exploration::EpsilonGreedy(schedule.parse().map_err(invalid)?)
+                }
+                (None, Some(temperatures)) => match temperatures.split_once(':') {
+                    Some((start, end)) => Exploration::Boltzmann {
+                        start: start.parse().map_err(|_| {
+                            invalid(format!("invalid temperature '{}'", temperatures))
+                        })?,
+                        end: end.parse().map_err(|_| {
+                            invalid(format!("invalid temperature '{}'", temperatures))
+                        })?,
+                    },
+                    None => {
+                        return Err(invalid(format!(
+                            "--boltzmann expects START:END, not '{}'",
+                            temperatures
+                        )));
+                    }
+                },
+                (None, None) => config.exploration,
+            };
+            config.initial_value = options.get("--initial-value", config.initial_value)?;
+            config.learner = options.get("--learner", config.learner)?;
+            config.encoder = match options.values.get("--encoder").map(String::as_str) {
+                None => config.encoder,
+                Some("classic") => StateEncoder::Classic,
+                Some("composition") => StateEncoder::Composition,
+                Some("count") => StateEncoder::CountAugmented,
+                Some(other) => return Err(invalid(format!("unknown encoder '{}'", other))),
+            };
+            config.rules = options.rules_over(config.rules)?;
+            if let Some(seed) = options.get_optional("--seed")? {
+                config.seed = Some(seed);
+            }
+            config.validate()?;
+            Ok(Command::Train(TrainOptions {
+                config,
+                output,
+                checkpoints,
+                resume,
+            }))
+        }
+        "play" => {
+            let options = Options::read(
+                rest,
+                &with_rules(&["--hint", "--bet", "--bankroll"]),
+                &RULE_SWITCHES,
+                0,
+            )?;
+            let bet: f32 = options.get("--bet", 1.0)?;
+            let bankroll: Option<f32> = options.get_optional("--bankroll")?;
+            if !(bet.is_finite() && bet > 0.0)
+                || bankroll.is_some_and(|bankroll| !bankroll.is_finite() || bankroll < bet)
+            {
+                return Err(invalid(
+                    "--bet must be positive and not above --bankroll, both finite".to_string(),
+                ));
+            }
+            Ok(Command::Play(PlayOptions {
+                rules: options.rules()?,
+                hint: options.values.get("--hint").cloned(),
+                bet,
+                bankroll,
+            }))
+        }
+        "table" => {
+            let options = Options::read(
+                rest,
+                &with_rules(&["--seats", "--rounds", "--bet", "--seed"]),
+                &RULE_SWITCHES,
+                0,
+            )?;
+            let table = TableOptions {
+                seats: options
+                    .get("--seats", "human".to_string())?
+                    .split(',')
+                    .map(|seat| seat.trim().to_string())
+                    .collect(),
+                rounds: options.get("--rounds", DEFAULT_ROUNDS)?,
+                bet: options.get("--bet", 1.0)?,
+                seed: options.get_optional("--seed")?,
+                rules: options.rules()?,
+            };
+            if table.seats.len() > MAX_SEATS || table.seats.iter().any(|seat| seat.is_empty()) {
+                return Err(invalid(format!(
+                    "--seats takes 1 to {} policies separated by commas",
+                    MAX_SEATS
+                )));
+            }
+            if table.rounds == 0 || !(table.bet.is_finite() && table.bet > 0.0) {
+                return Err(invalid("--rounds and --bet must be positive".to_string()));
+            }
+            Ok(Command::Table(table))
+        }
+        "eval" => {
+            let options = Options::read(
+                rest,
+                &with_rules(&["--hands", "--seed", "--chart"]),
+                &RULE_SWITCHES,
+                1,
+            )?;
+            let hands = options.get("--hands", DEFAULT_HANDS)?;
+            if hands == 0 {
+                return Err(invalid("--hands must be at least 1".to_string()));
+            }
+            let chart = options.get_optional("--chart")?;
+            if chart.is_some() && options.policy() != "basic" {
+                return Err(invalid(
+                    "--chart only goes with the basic policy".to_string(),
+                ));
+            }
+            Ok(Command::Eval(EvalOptions {
+                policy: options.policy(),
+                hands,
+                seed: options.get("--seed", 0)?,
+                chart,
+                rules: options.rules()?,
+            }))
+        }
+        "advise" => {
+            let options =
+                Options::read(rest, &with_rules(&["--cards", "--up"]), &RULE_SWITCHES, 1)?;
+            let cards = options
+                .values
+                .get("--cards")
+                .ok_or_else(|| invalid("advise needs --cards".to_string()))?
+                .split(',')
+                .map(|card| parse_card(card.trim()))
+                .collect::<Result<Vec<Card>, _>>()?;
+            if cards.len() < 2 {
+                return Err(invalid("--cards needs at least two cards".to_string()));
+            }
+            // a hand that does not bust holds 21 cards at most, the shoe checks the ranks left
+            let hard: usize = cards.iter().map(|card| card.value() as usize).sum();
+            if hard > 21 {
+                return Err(invalid(format!("--cards makes a bust hand of {}", hard)));
+            }
+            let up_card = parse_card(
+                options
+                    .values
+                    .get("--up")
+                    .ok_or_else(|| invalid("advise needs --up".to_string()))?,
+            )?;
+            Ok(Command::Advise(AdviseOptions {
+                policy: options.policy(),
+                cards,
+                up_card,
+                rules: options.rules()?,
+            }))
+        }
+        "export" => {
+            let options = Options::read(
+                rest,
+                &with_rules(&["--output", "--compare"]),
+                &rule_switches(&["--json"]),
+                0,
+            )?;
+            Ok(Command::Export(ExportOptions {
+                rules: options.rules()?,
+                json: options.has("--json"),
+                output: options.values.get("--output").cloned(),
+                compare: options.values.get("--compare").cloned(),
+            }))
+        }
+        "session" => {
+            let options = Options::read(
+                rest,
+                &with_rules(&with_session(&["--hands"])),
+                &RULE_SWITCHES,
+                1,
+            )?;
+            Ok(Command::Session(SessionOptions {
+                policy: options.policy(),
+                session: options.session(options.get("--hands", DEFAULT_SESSION_HANDS)?)?,
+                rules: options.rules()?,
+            }))
+        }
+        "risk" => {
+            let options = Options::read(
+                rest,
+                &with_rules(&with_session(&[
+                    "--hands",
+                    "--horizon",
+                    "--trajectories",
+                    "--kelly-fraction",
+                ])),
+                &RULE_SWITCHES,
+                1,
+            )?;
+            let risk = RiskOptions {
+                policy: options.policy(),
+                session: options.session(options.get("--horizon", DEFAULT_HORIZON)?)?,
+                hands: options.get("--hands", DEFAULT_HANDS)?,
+                kelly_fraction: options.get("--kelly-fraction", 0.5)?,
+                trajectories: options.get("--trajectories", DEFAULT_TRAJECTORIES)?,
+                rules: options.rules()?,
+            };
+            if risk.hands == 0
+                || risk.trajectories == 0
+                || !(risk.kelly_fraction > 0.0 && risk.kelly_fraction <= 1.0)
+            {
+                return Err(invalid(
+                    "--hands and --trajectories must be positive and --kelly-fraction in ]0, 1]"
+                        .to_string(),
+                ));
+            }
+            Ok(Command::Risk(risk))
+        }
+        _ => {
+            let options = Options::read(rest, &[], &[], 1)?;
+            let max_threads = options
+                .positionals
+                .first()
+                .map(|n| match n.parse::<usize>() {
+                    Ok(n) if n > 0 => Ok(n),
+                    _ => Err(invalid(format!("invalid number of threads '{}'", n))),
+                })
+                .transpose()?;
+            Ok(Command::Bench { max_threads })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schedule::EpsilonSchedule;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn train_options_and_rules() {
+        let Ok(Command::Train(options)) = parse(&args(
+            "train --episodes 500 --threads=4 --epsilon constant:0.1 --learning-rate visits \
+             --initial-value 1.5 --decks 2 --h17 --surrender late",
+        )) else {
+            panic!("train not parsed");
+        };
+        let config = options.config;
+        assert_eq!(config.episodes, 500);
+        assert_eq!(config.threads, 4);
+        assert_eq!(
+            config.exploration,
+            Exploration::EpsilonGreedy(EpsilonSchedule::Constant(0.1))
+        );
+        assert_eq!(config.learning_rate, LearningRate::Visits);
+        assert_eq!(config.initial_value, 1.5);
+        assert_eq!(config.rules.decks, 2);
+        assert!(config.rules.dealer_hits_soft_17);
+        assert_eq!(config.rules.surrender, Surrender::Late);
+        assert_eq!(options.output, DEFAULT_TABLE);
+    }
+
+    #[test]
+    fn config_file_is_overridden_by_the_options() {
+        let path = std::env::temp_dir().join(format!(
+            "black_jack_ia_{}_config_test.json",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            r#"{"episodes": 20, "gamma": 0.9, "learning_rate": "Visits",
+                "exploration": {"Boltzmann": {"start": 2.0, "end": 0.1}},
+                "rules": {"decks": 1, "dealer_hits_soft_17": true, "blackjack_payout": "SixToFive",
+                          "double": "AnyTwo", "double_after_split": true, "max_splits": 3,
+                          "resplit_aces": false, "hit_split_aces": false, "surrender": "None",
+                          "insurance": true, "dealer_peek": true, "no_hole_card": false}}"#,
+        )
+        .unwrap();
+        let line = format!("train --config {} --episodes 30 --no-das", path.display());
+        let Ok(Command::Train(options)) = parse(&args(&line)) else {
+            panic!("train not parsed");
+        };
+        let config = options.config;
+        assert_eq!(config.episodes, 30);
+        assert_eq!(config.threads, DEFAULT_THREADS);
+        assert_eq!(config.gamma, 0.9);
+        assert_eq!(config.learning_rate, LearningRate::Visits);
+        assert!(matches!(config.exploration, Exploration::Boltzmann { .. }));
+        assert_eq!(config.rules.decks, 1);
+        assert!(config.rules.dealer_hits_soft_17);
+        assert!(!config.rules.double_after_split);
+        // a rule switched on by the file is switched off by its opposite
+        let line = format!("train --config {} --s17", path.display());
+        let Ok(Command::Train(options)) = parse(&args(&line)) else {
+            panic!("train not parsed");
+        };
+        assert!(!options.config.rules.dealer_hits_soft_17);
+        assert!(options.config.rules.double_after_split);
+        std::fs::remove_file(path).unwrap();
+
+        for rules in [
+            RuleSet {
+                decks: 0,
+                ..RuleSet::default()
+            },
+            RuleSet {
+                max_splits: 200,
+                ..RuleSet::default()
+            },
+        ] {
+            let config = TrainingConfig {
+                rules,
+                ..TrainingConfig::default()
+            };
+            assert!(matches!(
+                config.validate(),
+                Err(BlackjackError::InvalidArgument(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn toml_config_file_is_chosen_by_its_extension() {
+        let path = std::env::temp_dir().join(format!(
+            "black_jack_ia_{}_toml_config_test.toml",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            r#"
+                episodes = 20
+                learner = "sarsa"
+                learning_rate = { Exponential = { start = 0.5, decay = 0.999, min = 0.01 } }
+                exploration = { EpsilonGreedy = { Visits = { c = 100.0 } } }
+                seed = 7
+
+                [rules]
+                decks = 2
+                dealer_hits_soft_17 = true
+                blackjack_payout = "ThreeToTwo"
+                double = "TenToEleven"
+                double_after_split = false
+                max_splits = 1
+                resplit_aces = false
+                hit_split_aces = false
+                surrender = "Late"
+                insurance = false
+                dealer_peek = true
+                no_hole_card = false
+            "#,
+        )
+        .unwrap();
+        let line = format!("train --config {} --threads 2", path.display());
+        let parsed = parse(&args(&line));
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, written.replace("episodes = 20", "episodes = [20")).unwrap();
+        let broken = TrainingConfig::load(path.to_str().unwrap());
+        std::fs::remove_file(path).unwrap();
+
+        let Ok(Command::Train(options)) = parsed else {
+            panic!("train not parsed");
+        };
+        let config = options.config;
+        assert_eq!((config.episodes, config.threads), (20, 2));
+        assert_eq!(config.learner, "sarsa");
+        assert!(matches!(
+            config.learning_rate,
+            LearningRate::Exponential { .. }
+        ));
+        assert_eq!(
+            config.exploration,
+            Exploration::EpsilonGreedy(EpsilonSchedule::Visits { c: 100.0 })
+        );
+        assert_eq!(config.seed, Some(7));
+        assert_eq!(config.rules.decks, 2);
+        assert_eq!(config.rules.surrender, Surrender::Late);
+        assert!(matches!(broken, Err(BlackjackError::Toml(_))));
+    }
+
+    #[test]
+    fn invalid_arguments_are_refused() {
+        for line in [
+            "fly",
+            "train --alpha 0",
+            "train --alpha 0.1 --learning-rate visits",
+            "train --epsilon linear:1:0.1 ",
+            "train --boltzmann 0",
+            "train --h17 --s17",
+            "train --boltzmann inf:0.1",
+            "train --epsilon visits:inf",
+            "train --epsilon inverse-time:1:inf",
+            "train --resume run.json --episodes 10",
+            "train --checkpoint-keep 0",
+            "train --checkpoint-minutes NaN",
+            "train --checkpoint-minutes inf",
+            "train --epsilon constant:0.1 --boltzmann 1:0.1",
+            "train --episodes",
+            "train --learner greedy",
+            "eval a.json b.json",
+            "eval random --chart chart.json",
+            "advise --cards 10,Z --up 6",
+            "advise --cards 10,10,5 --up 6",
+            "advise --cards A,A,A,A,A,A,A,A,A,A,A,A,A,A,A,A,A,A,A,A,A,A,A,A,A,A,A,A,A,A --up 6",
+            "export --decks 12",
+            "export --max-splits 255",
+            "play --bet NaN",
+            "play --bankroll inf",
+            "play --bankroll -5",
+            "session --count plus-minus",
+            "session --bankroll NaN",
+            "risk --bankroll inf",
+            "session --ramp 2:2,3",
+            "session --table-min 50 --table-max 20",
+            "risk --kelly-fraction 2",
+            "risk --kelly-fraction NaN",
+            "risk --trajectories 0",
+            "table --seats basic,,random",
+            "table --bet NaN",
+            "table --bet inf",
+            "table --seats basic,basic,basic,basic,basic,basic,basic,basic",
+            "bench 0",
+        ] {
+            assert!(
+                matches!(parse(&args(line)), Err(BlackjackError::InvalidArgument(_))),
+                "{}",
+                line
+            );
+        }
+    }
+
+    #[test]
+    fn no_command_trains_and_help_is_per_command() {
+        assert!(matches!(parse(&[]), Ok(Command::Train(_))));
+        assert!(matches!(
+            parse(&args("eval --help")),
+            Ok(Command::Help(Some(command))) if command == "eval"
+        ));
+    }
+}
@@ -0,0 +1,115 @@
+use crate::error::BlackjackError;
+use crate::learning::learner_from_name;
+use crate::rules::RuleSet;
+use crate::schedule::{Exploration, LearningRate};
+use crate::training::{Metadata, QTable, StateEncoder};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::Path;
+
+pub const DEFAULT_EPISODES: u64 = 1_000_000; // Number of iterations for training, per thread
+pub const DEFAULT_THREADS: usize = 20;
+
+// Read from JSON, or TOML for a `.toml` file, missing fields keep their default and the command
+// line overrides the rest. What changes the learned values goes in the table metadata.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TrainingConfig {
+    pub episodes: u64, // per thread
+    pub threads: usize,
+    pub learner: String,
+    pub encoder: StateEncoder,
+    pub rules: RuleSet,
+    pub learning_rate: LearningRate,
+    pub gamma: f32,
+    pub exploration: Exploration,
+    // value of the actions never tried, above the best return it makes the agent try them all
+    pub initial_value: f32,
+    pub seed: Option<u64>, // random when not given
+}
+
+impl Default for TrainingConfig {
+    fn default() -> Self {
+        let metadata = Metadata::default();
+        TrainingConfig {
+            episodes: DEFAULT_EPISODES,
+            threads: DEFAULT_THREADS,
+            learner: "q-learning".to_string(),
+            encoder: StateEncoder::Classic,
+            rules: metadata.rules,
+            learning_rate: metadata.learning_rate,
+            gamma: metadata.gamma,
+            exploration: metadata.exploration,
+            initial_value: metadata.initial_value,
+            seed: None,
+        }
+    }
+}
+
+impl TrainingConfig {
+    pub fn load(path: &str) -> Result<TrainingConfig, BlackjackError> {
+        let is_toml = Path::new(path)
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("toml"));
+        let config: TrainingConfig = if is_toml {
+            toml::from_str(&fs::read_to_string(path)?)?
+        } else {
+            serde_json::from_reader(BufReader::new(File::open(path)?))?
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), BlackjackError> {
+        let invalid = BlackjackError::InvalidArgument;
+        if self.episodes == 0 || self.threads == 0 {
+            return Err(invalid(
+                "episodes and threads must be at least 1".to_string(),
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.gamma) {
+            return Err(invalid(format!(
+                "gamma must be in [0, 1], not {}",
+                self.gamma
+            )));
+        }
+        if !self.initial_value.is_finite() {
+            return Err(invalid(format!(
+                "invalid initial value {}",
+                self.initial_value
+            )));
+        }
+        // same limits as --decks and --max-splits
+        if !(1..=8).contains(&self.rules.decks) {
+            return Err(invalid(format!(
+                "decks must be between 1 and 8, not {}",
+                self.rules.decks
+            )));
+        }
+        if self.rules.max_splits > 7 {
+            return Err(invalid(format!(
+                "max_splits must be between 0 and 7, not {}",
+                self.rules.max_splits
+            )));
+        }
+        if learner_from_name(&self.learner).is_none() {
+            return Err(invalid(format!("unknown learner '{}'", self.learner)));
+        }
+        self.learning_rate.validate().map_err(invalid)?;
+        self.exploration.validate().map_err(invalid)
+    }
+
+    // Empty table that will be trained with this configuration
+    pub fn table(&self) -> QTable {
+        let mut table = QTable::with_encoder(self.rules.clone(), self.encoder);
+        if let Some(learner) = learner_from_name(&self.learner) {
+            table.metadata.algorithm = learner.name().to_string();
+        }
+        table.metadata.learning_rate = self.learning_rate;
+        table.metadata.gamma = self.gamma;
+        table.metadata.exploration = self.exploration;
+        table.metadata.initial_value = self.initial_value;
+        table
+    }
+}
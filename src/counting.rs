@@ -0,0 +1,147 @@
+use crate::shoe::Shoe;
+
+// Sum of the tags of the cards shown since the shuffle (Shoe::seen), divided by the decks left for the true count
+pub trait CountingSystem: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // Tag of a card value, 1 for the ace up to 10
+    fn tag(&self, value: u8) -> f32;
+
+    // Running count at the shuffle, different from 0 for the unbalanced systems only
+    fn initial_count(&self, _decks: u8) -> f32 {
+        0.0
+    }
+
+    // A balanced system counts 0 once the whole shoe has been seen
+    fn is_balanced(&self) -> bool {
+        (1..=10)
+            .map(|value| self.tag(value) * if value == 10 { 4.0 } else { 1.0 })
+            .sum::<f32>()
+            == 0.0
+    }
+
+    fn running_count(&self, shoe: &Shoe) -> f32 {
+        self.initial_count(shoe.decks())
+            + shoe
+                .seen()
+                .iter()
+                .zip(1..=10)
+                .map(|(&seen, value)| seen as f32 * self.tag(value))
+                .sum::<f32>()
+    }
+
+    // Count of the cards seen per deck left, without the initial count of the unbalanced systems
+    fn true_count(&self, shoe: &Shoe) -> f32 {
+        let count = self.running_count(shoe) - self.initial_count(shoe.decks());
+        count / shoe.decks_remaining().max(0.5)
+    }
+}
+
+// System given by a table of tags, ace first
+pub struct Tags {
+    pub name: &'static str,
+    pub tags: [f32; 10],
+    pub initial: f32, // running count at the shuffle for each deck after the first
+}
+
+impl CountingSystem for Tags {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn tag(&self, value: u8) -> f32 {
+        self.tags[value as usize - 1]
+    }
+
+    fn initial_count(&self, decks: u8) -> f32 {
+        self.initial * (decks.max(1) - 1) as f32
+    }
+}
+
+pub const HI_LO: Tags = Tags {
+    name: "hi-lo",
+    tags: [-1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 0.0, 0.0, 0.0, -1.0],
+    initial: 0.0,
+};
+// Knock-out : the 7 counts too, the count starts at 4 - 4 × decks and is not divided
+pub const KO: Tags = Tags {
+    name: "ko",
+    tags: [-1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 0.0, 0.0, -1.0],
+    initial: -4.0,
+};
+pub const HI_OPT_I: Tags = Tags {
+    name: "hi-opt-1",
+    tags: [0.0, 0.0, 1.0, 1.0, 1.0, 1.0, 0.0, 0.0, 0.0, -1.0],
+    initial: 0.0,
+};
+pub const HI_OPT_II: Tags = Tags {
+    name: "hi-opt-2",
+    tags: [0.0, 1.0, 1.0, 2.0, 2.0, 1.0, 1.0, 0.0, 0.0, -2.0],
+    initial: 0.0,
+};
+pub const OMEGA_II: Tags = Tags {
+    name: "omega-2",
+    tags: [0.0, 1.0, 1.0, 2.0, 2.0, 2.0, 1.0, 0.0, -1.0, -2.0],
+    initial: 0.0,
+};
+pub const ZEN: Tags = Tags {
+    name: "zen",
+    tags: [-1.0, 1.0, 1.0, 2.0, 2.0, 2.0, 1.0, 0.0, 0.0, -2.0],
+    initial: 0.0,
+};
+pub const WONG_HALVES: Tags = Tags {
+    name: "wong-halves",
+    tags: [-1.0, 0.5, 1.0, 1.0, 1.5, 1.0, 0.5, 0.0, -0.5, -1.0],
+    initial: 0.0,
+};
+
+pub const SYSTEMS: [&Tags; 7] = [
+    &HI_LO,
+    &KO,
+    &HI_OPT_I,
+    &HI_OPT_II,
+    &OMEGA_II,
+    &ZEN,
+    &WONG_HALVES,
+];
+
+// Built-in system from its name, see SYSTEMS
+pub fn system_from_name(name: &str) -> Option<&'static dyn CountingSystem> {
+    SYSTEMS
+        .iter()
+        .find(|system| system.name == name)
+        .map(|system| *system as &'static dyn CountingSystem)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::GameState;
+    use crate::rules::RuleSet;
+
+    #[test]
+    fn only_ko_is_unbalanced() {
+        for system in SYSTEMS {
+            assert_eq!(system.is_balanced(), system.name != "ko", "{}", system.name);
+        }
+        assert_eq!(KO.initial_count(6), -20.0);
+    }
+
+    #[test]
+    fn hole_card_is_counted_once_turned_over() {
+        let mut game_state = GameState::seeded(RuleSet::default(), 8);
+        game_state.new_round();
+        game_state.deal().unwrap();
+        let shown: f32 = game_state
+            .discard
+            .iterator()
+            .iter()
+            .map(|card| HI_LO.tag(card.value()))
+            .sum();
+        assert_eq!(HI_LO.running_count(&game_state.shoe), shown);
+
+        game_state.croupier_play().unwrap();
+        let seen: u16 = game_state.shoe.seen().iter().sum();
+        assert_eq!(seen as usize, game_state.discard.len());
+    }
+}
@@ -0,0 +1,251 @@
+use crate::card::PackOfCards;
+use crate::error::BlackjackError;
+use crate::game::{Action, GameState};
+use crate::rules::RuleSet;
+use crate::shoe::Shoe;
+use crate::training::{MAX_TRUE_COUNT, State, StateEncoder};
+use std::fmt::Display;
+use std::ops::RangeInclusive;
+
+/*
+    `reset` deals a round, `step` plays one decision. After a split the steps go
+    on hand after hand, `info.hand_over` marks the last decision of a hand and
+    `info.hand_rewards` splits the reward of the last step between the hands.
+*/
+pub trait Environment {
+    type Observation;
+
+    // Start a new round, from a shoe shuffled with `seed` when there is one
+    fn reset(&mut self, seed: Option<u64>) -> Result<Self::Observation, BlackjackError>;
+
+    fn step(
+        &mut self,
+        action: Action,
+    ) -> Result<(Self::Observation, f32, bool, Info), BlackjackError>;
+
+    fn legal_actions(&self) -> Vec<Action>;
+
+    fn observation_space(&self) -> ObservationSpace;
+}
+
+// What a step tells besides the reward
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Info {
+    pub hands: usize,               // hands of the player, more than one after a split
+    pub hand: usize,                // hand the action of the step was played on
+    pub hand_over: bool,            // that hand has no decision left
+    pub current_hand: usize,        // hand the next decision is for
+    pub croupier_total: Option<u8>, // total of the dealer once the round is over
+    pub hand_rewards: Vec<f32>,     // money won by each hand once the round is over
+}
+
+// What the observations are made of, they are given by the encoder
+#[derive(Clone, Debug, PartialEq)]
+pub struct ObservationSpace {
+    pub encoder: StateEncoder,
+    pub croupier_card: RangeInclusive<u8>, // value of the up card, 1 for the ace
+    pub true_count: Option<RangeInclusive<i8>>,
+    pub actions: Vec<Action>, // every action a step can be given
+}
+
+impl Display for ObservationSpace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let player = match self.encoder {
+            StateEncoder::Classic | StateEncoder::CountAugmented => {
+                "total of the hand, soft or hard, value of a pair"
+            }
+            StateEncoder::Composition => "sorted values of the cards of the hand",
+        };
+        writeln!(f, "player: {}", player)?;
+        writeln!(
+            f,
+            "dealer up card: {} to {}",
+            self.croupier_card.start(),
+            self.croupier_card.end()
+        )?;
+        if let Some(true_count) = &self.true_count {
+            writeln!(
+                f,
+                "Hi-Lo true count: {} to {}",
+                true_count.start(),
+                true_count.end()
+            )?;
+        }
+        writeln!(f, "insurance taken and legal actions of the hand")?;
+        let actions: Vec<String> = self.actions.iter().map(|a| a.to_string()).collect();
+        writeln!(f, "actions: {}", actions.join(", "))
+    }
+}
+
+// Blackjack against the dealer, the observations are the states of a QTable
+pub struct BlackjackEnv {
+    pub game_state: GameState, // the shoe is kept from one round to the next
+    pub encoder: StateEncoder,
+    pub bet: f32,
+    done: bool,
+}
+
+impl BlackjackEnv {
+    pub fn new(rules: RuleSet, encoder: StateEncoder) -> BlackjackEnv {
+        BlackjackEnv::with_game(GameState::with_rules(rules), encoder)
+    }
+
+    pub fn with_game(game_state: GameState, encoder: StateEncoder) -> BlackjackEnv {
+        BlackjackEnv {
+            game_state,
+            encoder,
+            bet: 1.0,
+            done: true,
+        }
+    }
+
+    fn info(&self, hand: usize) -> Info {
+        Info {
+            hands: self.game_state.hands.len(),
+            hand,
+            hand_over: self.done || self.game_state.current_hand != hand,
+            current_hand: self.game_state.current_hand,
+            croupier_total: self
+                .done
+                .then(|| self.game_state.croupier_cards.value().total()),
+            hand_rewards: if self.done {
+                self.game_state.hand_results(self.bet)
+            } else {
+                Vec::new()
+            },
+        }
+    }
+}
+
+impl Environment for BlackjackEnv {
+    type Observation = State;
+
+    fn reset(&mut self, seed: Option<u64>) -> Result<State, BlackjackError> {
+        if let Some(seed) = seed {
+            let shoe = &self.game_state.shoe;
+            self.game_state.shoe =
+                Shoe::seeded(shoe.decks(), shoe.penetration(), shoe.burn_cards(), seed);
+            self.game_state.discard = PackOfCards::new();
+        }
+        self.game_state.new_round();
+        self.game_state.bet = self.bet;
+        self.done = false;
+        self.game_state.deal()?;
+        Ok(self.encoder.encode(&self.game_state))
+    }
+
+    fn step(&mut self, action: Action) -> Result<(State, f32, bool, Info), BlackjackError> {
+        if self.done {
+            return Err(BlackjackError::IllegalAction {
+                action,
+                reason: "The round is over, reset the environment",
+            });
+        }
+        let hand = self.game_state.current_hand;
+        if self.game_state.continue_game {
+            self.game_state = self.game_state.play(action)?;
+        }
+        let mut reward = 0.0;
+        if !self.game_state.continue_game {
+            self.game_state.croupier_play()?;
+            reward = self.game_state.results(self.bet);
+            self.done = true;
+        }
+        let observation = self.encoder.encode(&self.game_state);
+        Ok((observation, reward, self.done, self.info(hand)))
+    }
+
+    fn legal_actions(&self) -> Vec<Action> {
+        self.game_state.legal_actions()
+    }
+
+    fn observation_space(&self) -> ObservationSpace {
+        let max = MAX_TRUE_COUNT as i8;
+        ObservationSpace {
+            encoder: self.encoder,
+            croupier_card: 1..=10,
+            true_count: (self.encoder == StateEncoder::CountAugmented).then_some(-max..=max),
+            actions: Action::ALL.to_vec(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rounds_end_with_a_reward_and_replay_with_the_seed() {
+        let mut env = BlackjackEnv::new(RuleSet::default(), StateEncoder::Classic);
+        assert!(env.observation_space().true_count.is_none());
+        assert!(env.step(Action::Stand).is_err());
+
+        for round in 0..500 {
+            let first = env.reset(Some(round)).unwrap();
+            assert_eq!(env.reset(Some(round)).unwrap(), first);
+            let (mut reward, mut done) = (0.0, false);
+            while !done {
+                let action = env
+                    .legal_actions()
+                    .first()
+                    .copied()
+                    .unwrap_or(Action::Stand);
+                let info;
+                (_, reward, done, info) = env.step(action).unwrap();
+                assert_eq!(info.croupier_total.is_some(), done);
+            }
+            assert!(reward.abs() <= 8.0);
+            assert!(env.legal_actions().is_empty());
+            assert!(env.step(Action::Stand).is_err());
+        }
+    }
+
+    #[test]
+    fn seeded_reset_keeps_the_shoe_settings() {
+        let rules = RuleSet {
+            decks: 1,
+            ..RuleSet::default()
+        };
+        let game_state = GameState::with_shoe(rules, Shoe::seeded(1, 1.0, 0, 3));
+        let mut env = BlackjackEnv::with_game(game_state, StateEncoder::Classic);
+        env.reset(Some(7)).unwrap();
+        let shoe = &env.game_state.shoe;
+        assert_eq!(
+            (shoe.decks(), shoe.penetration(), shoe.burn_cards()),
+            (1, 1.0, 0)
+        );
+    }
+
+    #[test]
+    fn each_split_hand_ends_with_its_own_reward() {
+        let mut env = BlackjackEnv::new(RuleSet::default(), StateEncoder::Classic);
+        let mut splits = 0;
+        for round in 0..5_000 {
+            env.reset(Some(round)).unwrap();
+            if !env.legal_actions().contains(&Action::Split) {
+                continue;
+            }
+            splits += 1;
+            let (_, mut reward, mut done, mut info) = env.step(Action::Split).unwrap();
+            let mut hands_over = Vec::new();
+            loop {
+                assert_eq!(info.hand_over, done || info.current_hand != info.hand);
+                if info.hand_over {
+                    hands_over.push(info.hand);
+                }
+                if done {
+                    break;
+                }
+                assert_eq!(reward, 0.0);
+                assert!(info.hand_rewards.is_empty());
+                (_, reward, done, info) = env.step(Action::Stand).unwrap();
+            }
+            // a hand played by the game alone (split aces, 21) has no step of its own
+            assert!(hands_over.windows(2).all(|w| w[0] < w[1]));
+            assert!(hands_over.iter().all(|&hand| hand < info.hands));
+            assert_eq!(info.hand_rewards.len(), info.hands);
+            assert_eq!(info.hand_rewards.iter().sum::<f32>(), reward);
+        }
+        assert!(splits > 50);
+    }
+}
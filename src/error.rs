@@ -0,0 +1,82 @@
+use crate::game::Action;
+use crate::training::State;
+use std::fmt::Display;
+
+#[derive(Debug)]
+pub enum BlackjackError {
+    // no more cards to deal
+    EmptyShoe,
+    // action refused by the rules
+    IllegalAction {
+        action: Action,
+        reason: &'static str,
+    },
+    // asked for an action once the game is over
+    NoLegalAction(State),
+    // state not found in the Q-table
+    MissingState(State),
+    // NaN or infinite reward
+    InvalidReward(f32),
+    // bad command line argument
+    InvalidArgument(String),
+    // table or checkpoint file read whole but not usable
+    InvalidTable(String),
+    Io(std::io::Error),
+    Parse(serde_json::Error),
+    // configuration file written in TOML
+    Toml(toml::de::Error),
+}
+
+impl Display for BlackjackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BlackjackError::EmptyShoe => write!(f, "No more cards"),
+            BlackjackError::IllegalAction { action, reason } => {
+                write!(f, "Illegal action {} : {}", action, reason)
+            }
+            BlackjackError::NoLegalAction(state) => {
+                write!(f, "No legal action in state {:?}", state)
+            }
+            BlackjackError::MissingState(state) => {
+                write!(f, "State {:?} not found in QTable", state)
+            }
+            BlackjackError::InvalidReward(reward) => {
+                write!(f, "Invalid reward {}, check your game logic", reward)
+            }
+            BlackjackError::InvalidArgument(message) => write!(f, "{}", message),
+            BlackjackError::InvalidTable(message) => write!(f, "Invalid Q-table : {}", message),
+            BlackjackError::Io(e) => write!(f, "I/O error : {}", e),
+            BlackjackError::Parse(e) => write!(f, "Parse error : {}", e),
+            BlackjackError::Toml(e) => write!(f, "Parse error : {}", e),
+        }
+    }
+}
+
+impl std::error::Error for BlackjackError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BlackjackError::Io(e) => Some(e),
+            BlackjackError::Parse(e) => Some(e),
+            BlackjackError::Toml(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for BlackjackError {
+    fn from(e: std::io::Error) -> Self {
+        BlackjackError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for BlackjackError {
+    fn from(e: serde_json::Error) -> Self {
+        BlackjackError::Parse(e)
+    }
+}
+
+impl From<toml::de::Error> for BlackjackError {
+    fn from(e: toml::de::Error) -> Self {
+        BlackjackError::Toml(e)
+    }
+}
@@ -0,0 +1,325 @@
+use crate::error::BlackjackError;
+use crate::game::*;
+use crate::rules::RuleSet;
+use crate::solver::StrategyChart;
+use crate::training::{QTable, greedy_action};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+// Way of playing a hand : the action chosen in a game, always among the legal ones
+pub trait Policy {
+    fn name(&self) -> String;
+
+    fn choose(&mut self, game_state: &GameState) -> Action;
+}
+
+// Best action of a trained table, without exploration
+pub struct GreedyPolicy<'a> {
+    pub table: &'a QTable,
+}
+
+impl Policy for GreedyPolicy<'_> {
+    fn name(&self) -> String {
+        format!("q-table ({})", self.table.metadata.algorithm)
+    }
+
+    fn choose(&mut self, game_state: &GameState) -> Action {
+        let state = self.table.encode(game_state);
+        let action = match self.table.states.get(&state) {
+            Some(values) => greedy_action(&state, &values).ok(),
+            None => None, // never seen during the training
+        };
+        let legal = game_state.legal_actions();
+        match action {
+            Some(action) => action,
+            // an ace waiting to be resplit cannot stand
+            None if !legal.is_empty() && !legal.contains(&Action::Stand) => legal[0],
+            None => Action::Stand,
+        }
+    }
+}
+
+// Action given by a strategy chart, for example the one of solver::solve
+pub struct ChartPolicy {
+    pub chart: StrategyChart,
+}
+
+impl Policy for ChartPolicy {
+    fn name(&self) -> String {
+        "basic strategy".to_string()
+    }
+
+    fn choose(&mut self, game_state: &GameState) -> Action {
+        let cards = game_state.get_player_cards();
+        let value = cards.value();
+        let pair = match (cards.get_card(0), cards.get_card(1)) {
+            (Some(first), Some(second)) if cards.len() == 2 && first.value() == second.value() => {
+                Some(first.value())
+            }
+            _ => None,
+        };
+        let up = game_state.get_croupier_first_card().unwrap().value();
+        self.chart.action(
+            value.total(),
+            value.is_soft(),
+            pair,
+            up,
+            &game_state.legal_actions(),
+        )
+    }
+}
+
+// Draws until 17 like the dealer
+pub struct MimicDealer;
+
+impl Policy for MimicDealer {
+    fn name(&self) -> String {
+        "mimic the dealer".to_string()
+    }
+
+    fn choose(&mut self, game_state: &GameState) -> Action {
+        let value = game_state.get_player_cards().value();
+        if game_state.rules.croupier_must_draw(&value) {
+            Action::Draw
+        } else {
+            Action::Stand
+        }
+    }
+}
+
+// Only draws when the next card cannot bust the hand
+pub struct NeverBust;
+
+impl Policy for NeverBust {
+    fn name(&self) -> String {
+        "never bust".to_string()
+    }
+
+    fn choose(&mut self, game_state: &GameState) -> Action {
+        let value = game_state.get_player_cards().value();
+        if value.hard_total() <= 11 && value.total() < 21 {
+            Action::Draw
+        } else {
+            Action::Stand
+        }
+    }
+}
+
+// Any legal action, the baseline of the untrained agent
+pub struct RandomPolicy {
+    rng: StdRng,
+}
+
+impl RandomPolicy {
+    pub fn new(seed: u64) -> RandomPolicy {
+        RandomPolicy {
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+}
+
+impl Policy for RandomPolicy {
+    fn name(&self) -> String {
+        "random".to_string()
+    }
+
+    fn choose(&mut self, game_state: &GameState) -> Action {
+        game_state
+            .legal_actions()
+            .choose(&mut self.rng)
+            .copied()
+            .unwrap_or(Action::Stand)
+    }
+}
+
+// Mean and variance of values added one after the other (Welford), the returns of hands for example
+#[derive(Clone, Debug, Default)]
+pub struct RunningStats {
+    pub count: u64,
+    pub mean: f64,
+    m2: f64,
+}
+
+impl RunningStats {
+    pub fn add(&mut self, value: f64) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    // Variance of the sample, 0 below two values
+    pub fn variance(&self) -> f64 {
+        if self.count > 1 {
+            self.m2 / (self.count - 1) as f64
+        } else {
+            0.0
+        }
+    }
+
+    pub fn std_dev(&self) -> f64 {
+        self.variance().sqrt()
+    }
+}
+
+// Deal, play the decisions of `policy` (each one shown to `decided`), then the dealer
+pub fn play_round(
+    game_state: &mut GameState,
+    policy: &mut dyn Policy,
+    mut decided: impl FnMut(Action),
+) -> Result<(), BlackjackError> {
+    game_state.deal()?;
+    while game_state.continue_game {
+        let action = policy.choose(game_state);
+        decided(action);
+        *game_state = game_state.play(action)?;
+    }
+    game_state.croupier_play()
+}
+
+// Results of a policy over many hands, the return of a hand is the money won for a bet of 1
+#[derive(Clone, Debug, Default)]
+pub struct Evaluation {
+    pub policy: String,
+    pub hands: u64,
+    pub mean: f64,
+    pub std_dev: f64,
+    pub wins: u64,
+    pub pushes: u64,
+    pub losses: u64,
+    pub busts: u64,      // hands where at least one hand of the player went over 21
+    pub blackjacks: u64, // blackjacks of the player
+    pub actions: BTreeMap<Action, u64>,
+}
+
+impl Evaluation {
+    // 95% confidence interval of the mean return per hand
+    pub fn confidence_interval(&self) -> (f64, f64) {
+        let margin = 1.96 * self.std_dev / (self.hands.max(1) as f64).sqrt();
+        (self.mean - margin, self.mean + margin)
+    }
+
+    fn rate(&self, count: u64) -> f64 {
+        count as f64 / self.hands.max(1) as f64
+    }
+}
+
+impl Display for Evaluation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (low, high) = self.confidence_interval();
+        writeln!(f, "Policy: {} ({} hands)", self.policy, self.hands)?;
+        writeln!(
+            f,
+            "Mean return per hand: {:+.4} (95% CI {:+.4} .. {:+.4}), standard deviation {:.4}",
+            self.mean, low, high, self.std_dev
+        )?;
+        writeln!(
+            f,
+            "Win {:.2}%  Push {:.2}%  Loss {:.2}%  Bust {:.2}%  Blackjack {:.2}%",
+            100.0 * self.rate(self.wins),
+            100.0 * self.rate(self.pushes),
+            100.0 * self.rate(self.losses),
+            100.0 * self.rate(self.busts),
+            100.0 * self.rate(self.blackjacks)
+        )?;
+        let decisions: u64 = self.actions.values().sum();
+        write!(f, "Actions:")?;
+        for (action, count) in &self.actions {
+            write!(
+                f,
+                "  {} {:.2}%",
+                action,
+                100.0 * *count as f64 / decisions.max(1) as f64
+            )?;
+        }
+        writeln!(f)
+    }
+}
+
+// `n_hands` hands of 1 from a shoe shuffled with `seed`, the same cards for every policy that plays alike
+pub fn evaluate(
+    policy: &mut dyn Policy,
+    rules: &RuleSet,
+    n_hands: u64,
+    seed: u64,
+) -> Result<Evaluation, BlackjackError> {
+    let mut game_state = GameState::seeded(rules.clone(), seed);
+    let mut evaluation = Evaluation {
+        policy: policy.name(),
+        ..Evaluation::default()
+    };
+    let mut returns = RunningStats::default();
+
+    for _ in 0..n_hands {
+        game_state.new_round();
+        play_round(&mut game_state, policy, |action| {
+            *evaluation.actions.entry(action).or_insert(0) += 1;
+        })?;
+        let reward = game_state.results(1.0) as f64;
+
+        // a blackjack is never split, the first hand is still the one dealt
+        if game_state.hands[0].is_blackjack() {
+            evaluation.blackjacks += 1;
+        }
+        if game_state
+            .hands
+            .iter()
+            .any(|hand| hand.cards.value().is_bust())
+        {
+            evaluation.busts += 1;
+        }
+        if reward > 0.0 {
+            evaluation.wins += 1;
+        } else if reward < 0.0 {
+            evaluation.losses += 1;
+        } else {
+            evaluation.pushes += 1;
+        }
+        returns.add(reward);
+    }
+    evaluation.hands = returns.count;
+    evaluation.mean = returns.mean;
+    evaluation.std_dev = returns.std_dev();
+    Ok(evaluation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_evaluation() {
+        let rules = RuleSet::default();
+        let first = evaluate(&mut RandomPolicy::new(7), &rules, 2_000, 42).unwrap();
+        let second = evaluate(&mut RandomPolicy::new(7), &rules, 2_000, 42).unwrap();
+        assert_eq!(first.mean, second.mean);
+        assert_eq!(first.actions, second.actions);
+        assert_eq!(first.wins + first.pushes + first.losses, 2_000);
+    }
+
+    #[test]
+    fn never_bust_never_busts() {
+        let evaluation = evaluate(&mut NeverBust, &RuleSet::default(), 2_000, 1).unwrap();
+        assert_eq!(evaluation.busts, 0);
+        let (low, high) = evaluation.confidence_interval();
+        assert!(low < evaluation.mean && evaluation.mean < high);
+    }
+
+    #[test]
+    fn running_stats_match_the_two_pass_formulas() {
+        let values = [1.0, -1.0, 1.5, 0.0, -1.0, 2.0];
+        let mut stats = RunningStats::default();
+        for value in values {
+            stats.add(value);
+        }
+        let mean = values.iter().sum::<f64>() / 6.0;
+        let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / 5.0;
+        assert_eq!(stats.count, 6);
+        assert!((stats.mean - mean).abs() < 1e-12);
+        assert!((stats.variance() - variance).abs() < 1e-12);
+        assert_eq!(RunningStats::default().variance(), 0.0);
+    }
+}
@@ -1,6 +1,13 @@
 use crate::card::*;
+use crate::error::BlackjackError;
+use crate::evaluation::Policy;
+use crate::rules::{RuleSet, Surrender};
+use crate::shoe::*;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
 use std::io;
 use std::io::Write;
+use std::str::FromStr;
 
 /*
     Actions possibles du joueur au Blackjack :
@@ -8,66 +15,284 @@ use std::io::Write;
     2. Rester (Stand)
     3. Doubler la mise (Double Down)
     4. Prendre une assurance (Insurance)
-    //5. Séparer (Split) to implement later
+    5. Séparer (Split) une paire en deux mains
+    6. Abandonner (Surrender) et récupérer la moitié de la mise
 
 */
 
-#[derive(Clone, Debug, Copy, Eq, Hash, PartialEq)]
+#[derive(Clone, Debug, Copy, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum Action {
     Draw,
     Stand,
     Double,
     Insurance,
+    Split,
+    Surrender,
 }
 impl Action {
+    pub const ALL: [Action; 6] = [
+        Action::Draw,
+        Action::Stand,
+        Action::Double,
+        Action::Insurance,
+        Action::Split,
+        Action::Surrender,
+    ];
+
     pub fn into_index(&self) -> usize {
         match self {
             Action::Draw => 0,
             Action::Stand => 1,
             Action::Double => 2,
             Action::Insurance => 3,
+            Action::Split => 4,
+            Action::Surrender => 5,
         }
     }
+
+    pub fn from_index(index: usize) -> Option<Action> {
+        Action::ALL.get(index).copied()
+    }
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Action::ALL
+            .into_iter()
+            .find(|action| action.to_string() == s)
+            .ok_or_else(|| format!("unknown action '{}'", s))
+    }
+}
+
+impl Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let action_str = match self {
+            Action::Draw => "draw",
+            Action::Stand => "stand",
+            Action::Double => "double",
+            Action::Insurance => "insurance",
+            Action::Split => "split",
+            Action::Surrender => "surrender",
+        };
+        write!(f, "{}", action_str)
+    }
+}
+
+// One hand of the player, there are several of them after a split
+#[derive(Clone, Debug, Default)]
+pub struct PlayerHand {
+    pub cards: PackOfCards,
+    pub double: bool, // if the player has doubled down on this hand
+    pub split: bool,  // if the hand comes from a split
+}
+
+impl PlayerHand {
+    // A 21 made after a split is not a blackjack
+    pub fn is_blackjack(&self) -> bool {
+        !self.split && self.cards.value().is_blackjack()
+    }
+
+    fn is_split_aces(&self) -> bool {
+        self.split
+            && self
+                .cards
+                .get_card(0)
+                .is_some_and(|card| card.rank == Rank::Ace)
+    }
 }
 
 #[derive(Clone)]
 pub struct GameState {
     pub continue_game: bool,         // if the game is still ongoing
-    pub player_cards: PackOfCards,   // cards of the player
+    pub hands: Vec<PlayerHand>,      // hands of the player, played one after the other
+    pub current_hand: usize,         // index of the hand being played
     pub croupier_cards: PackOfCards, // cards of the dealer
-    pub packet: PackOfCards,         // packet of cards that have not been played
-    pub discard: PackOfCards,        // packet of cards that have been played
+    pub shoe: Shoe,                  // cards that have not been played, kept between hands
+    pub discard: PackOfCards,        // cards played since the last shuffle
     pub insurance: bool,             // if the player has taken insurance
-    pub double: bool,                // if the player has doubled down
+    pub bet: f32,                    // first bet of the round
+    pub bankroll: Option<f32>,       // money the player can put on the table, None for no limit
+    pub surrender: bool,             // if the player has surrendered
+    pub peeked: bool,                // if the dealer has checked his hole card for blackjack
+    pub rules: RuleSet,              // rules of the table
+}
+
+impl Default for GameState {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl GameState {
     pub fn new() -> GameState {
+        GameState::with_rules(RuleSet::default())
+    }
+
+    pub fn with_rules(rules: RuleSet) -> GameState {
+        let shoe = Shoe::new(rules.decks, DEFAULT_PENETRATION, DEFAULT_BURN_CARDS);
+        GameState::with_shoe(rules, shoe)
+    }
+
+    // Game whose shoe is always shuffled the same way for the same seed
+    pub fn seeded(rules: RuleSet, seed: u64) -> GameState {
+        let shoe = Shoe::seeded(rules.decks, DEFAULT_PENETRATION, DEFAULT_BURN_CARDS, seed);
+        GameState::with_shoe(rules, shoe)
+    }
+
+    pub fn with_shoe(rules: RuleSet, shoe: Shoe) -> GameState {
         GameState {
             continue_game: true,
-            player_cards: PackOfCards::new(),
+            hands: vec![PlayerHand::default()],
+            current_hand: 0,
             croupier_cards: PackOfCards::new(),
-            packet: PackOfCards::init(),
+            shoe,
             discard: PackOfCards::new(),
             insurance: false,
-            double: false,
+            bet: 1.0,
+            bankroll: None,
+            surrender: false,
+            peeked: false,
+            rules,
+        }
+    }
+
+    // Hand given card by card, the cards are taken out of the shoe and the dealer has peeked when he would
+    pub fn from_cards(
+        rules: RuleSet,
+        player: &[Card],
+        up_card: Card,
+    ) -> Result<GameState, BlackjackError> {
+        let mut game_state = GameState::with_rules(rules);
+        for card in player {
+            let card = game_state.shoe.take(card)?;
+            game_state.hands[0].cards.add_card(card);
+            game_state.discard.add_card(card);
+        }
+        let up_card = game_state.shoe.take(&up_card)?;
+        game_state.croupier_cards.add_card(up_card);
+        game_state.discard.add_card(up_card);
+        game_state.peeked =
+            up_card.rank != Rank::Ace && game_state.rules.surrender != Surrender::Early;
+        Ok(game_state)
+    }
+
+    // Clear the table for the next hand, the shoe is reshuffled if the cut card came out
+    pub fn new_round(&mut self) {
+        self.shoe.end_round();
+        if self.shoe.needs_shuffle() {
+            self.shoe.shuffle();
+            self.discard = PackOfCards::new();
         }
+        self.continue_game = true;
+        self.hands = vec![PlayerHand::default()];
+        self.current_hand = 0;
+        self.croupier_cards = PackOfCards::new();
+        self.insurance = false;
+        self.surrender = false;
+        self.peeked = false;
     }
 
     pub fn from(other: &GameState) -> GameState {
         GameState {
-            continue_game: other.continue_game.clone(),
-            player_cards: other.player_cards.clone(),
+            continue_game: other.continue_game,
+            hands: other.hands.clone(),
+            current_hand: other.current_hand,
             croupier_cards: other.croupier_cards.clone(),
-            packet: other.packet.clone(),
+            shoe: other.shoe.clone(),
             discard: other.discard.clone(),
-            insurance: other.insurance.clone(),
-            double: other.double.clone(),
+            insurance: other.insurance,
+            bet: other.bet,
+            bankroll: other.bankroll,
+            surrender: other.surrender,
+            peeked: other.peeked,
+            rules: other.rules.clone(),
         }
     }
 
+    // Deal in the casino order, without hole card (ENHC) the dealer gets his second card when he plays
+    pub fn deal(&mut self) -> Result<(), BlackjackError> {
+        let card = self.draw_card()?;
+        self.hands[0].cards.add_card(card);
+        let up_card = self.draw_card()?;
+        self.croupier_cards.add_card(up_card);
+        let card = self.draw_card()?;
+        self.hands[0].cards.add_card(card);
+        if !self.rules.no_hole_card {
+            // the hole card stays face down until the dealer plays
+            let card = self.draw_face_down()?;
+            self.croupier_cards.add_card(card);
+        }
+        self.after_deal();
+        Ok(())
+    }
+
+    // Blackjack of the player, even money and dealer peek once the first cards are on the table
+    pub fn after_deal(&mut self) {
+        let up_card_is_ace = self
+            .get_croupier_first_card()
+            .is_some_and(|card| card.rank == Rank::Ace);
+        if self.hands[0].is_blackjack() {
+            // with a dealer ace the player is offered even money first
+            self.continue_game = up_card_is_ace && self.rules.insurance;
+            if !self.continue_game {
+                self.peek();
+            }
+        } else if !up_card_is_ace && self.rules.surrender != Surrender::Early {
+            // with an ace the dealer waits for the insurance decision
+            self.peek();
+        }
+    }
+
+    // American peek : the dealer checks his hole card under an ace or a ten, a blackjack ends the round
+    fn peek(&mut self) {
+        if self.peeked {
+            return;
+        }
+        self.peeked = true;
+        if self.rules.dealer_peek && !self.rules.no_hole_card && self.croupier_has_blackjack() {
+            self.continue_game = false;
+        }
+    }
+
+    fn croupier_has_blackjack(&self) -> bool {
+        self.croupier_cards.value().is_blackjack()
+    }
+
+    // Card dealt face up, it goes to the discard once the round is over
+    pub fn draw_card(&mut self) -> Result<Card, BlackjackError> {
+        let reshuffled = self.shoe.remaining() == 0;
+        let card = self.shoe.pick()?;
+        if reshuffled {
+            self.reset_discard();
+        } else {
+            self.discard.add_card(card);
+        }
+        Ok(card)
+    }
+
+    // Hole card of the dealer, or his second card without hole card
+    pub fn draw_face_down(&mut self) -> Result<Card, BlackjackError> {
+        let reshuffled = self.shoe.remaining() == 0;
+        let card = self.shoe.pick_face_down()?;
+        if reshuffled {
+            self.reset_discard();
+        }
+        Ok(card)
+    }
+
+    // After a reshuffle in the round only the cards shown on the table are out of the shoe
+    fn reset_discard(&mut self) {
+        self.discard = PackOfCards::new();
+        for card in self.shoe.shown_in_play() {
+            self.discard.add_card(card);
+        }
+    }
+
+    // Cards of the hand being played
     pub fn get_player_cards(&self) -> &PackOfCards {
-        &self.player_cards
+        &self.hands[self.current_hand].cards
     }
 
     pub fn as_mut_ref(&mut self) -> &mut Self {
@@ -82,128 +307,369 @@ impl GameState {
         self.insurance
     }
 
-    pub fn play(&mut self, action: Action) -> Result<GameState, &str> {
+    // A pair of the same value can be split, up to the number of splits allowed by the rules
+    pub fn can_split(&self) -> bool {
+        let hand = &self.hands[self.current_hand];
+        match (hand.cards.get_card(0), hand.cards.get_card(1)) {
+            (Some(first), Some(second)) if hand.cards.len() == 2 => {
+                first.value() == second.value()
+                    && self.hands.len() <= self.rules.max_splits as usize
+                    && (!hand.is_split_aces() || self.rules.resplit_aces)
+            }
+            _ => false,
+        }
+    }
+
+    // Split aces that cannot be hit are only played again when they can be resplit
+    fn can_resplit_aces(&self) -> bool {
+        self.can_split() && self.can_afford(self.bet)
+    }
+
+    // Insurance is offered on a dealer ace, before the first decision
+    pub fn can_insure(&self) -> bool {
+        self.rules.insurance
+            && self
+                .get_croupier_first_card()
+                .is_some_and(|card| card.rank == Rank::Ace)
+            && !self.insurance
+            && !self.peeked
+            && self.hands.len() == 1
+            && self.hands[0].cards.len() == 2
+    }
+
+    // Surrender is only possible as the first decision, on the first two cards
+    pub fn can_surrender(&self) -> bool {
+        self.rules.surrender != Surrender::None
+            && self.hands.len() == 1
+            && self.hands[0].cards.len() == 2
+    }
+
+    // Limit the doubles, splits and insurance to the money the player has
+    pub fn set_bankroll(&mut self, bankroll: f32, bet: f32) {
+        self.bankroll = Some(bankroll);
+        self.bet = bet;
+    }
+
+    // Money already on the table for this round
+    fn committed(&self) -> f32 {
+        let mut committed: f32 = self
+            .hands
+            .iter()
+            .map(|hand| {
+                if hand.double {
+                    self.bet * 2.0
+                } else {
+                    self.bet
+                }
+            })
+            .sum();
+        if self.insurance {
+            committed += self.bet / 2.0;
+        }
+        committed
+    }
+
+    fn can_afford(&self, amount: f32) -> bool {
+        match self.bankroll {
+            Some(bankroll) => self.committed() + amount <= bankroll,
+            None => true,
+        }
+    }
+
+    // Actions allowed for the hand being played, empty once the game is over
+    pub fn legal_actions(&self) -> Vec<Action> {
+        if !self.continue_game {
+            return Vec::new();
+        }
+        let hand = &self.hands[self.current_hand];
+        let insurance = self.can_insure() && self.can_afford(self.bet / 2.0);
+        if hand.is_blackjack() {
+            // only even money can be taken on a blackjack
+            return if insurance {
+                vec![Action::Stand, Action::Insurance]
+            } else {
+                vec![Action::Stand]
+            };
+        }
+
+        if hand.is_split_aces() && !self.rules.hit_split_aces {
+            // a split ace only stops on the table to be split again
+            return if self.can_resplit_aces() {
+                vec![Action::Split]
+            } else {
+                Vec::new()
+            };
+        }
+
+        let mut actions = vec![Action::Draw, Action::Stand];
+        if hand.cards.len() == 2
+            && self.rules.can_double(&hand.cards.value())
+            && (!hand.split || self.rules.double_after_split)
+            && self.can_afford(self.bet)
+        {
+            actions.push(Action::Double);
+        }
+        if insurance {
+            actions.push(Action::Insurance);
+        }
+        if self.can_split() && self.can_afford(self.bet) {
+            actions.push(Action::Split);
+        }
+        if self.can_surrender() {
+            actions.push(Action::Surrender);
+        }
+        actions
+    }
+
+    pub fn play(&mut self, action: Action) -> Result<GameState, BlackjackError> {
         let mut new_state = GameState::from(self);
+        let index = new_state.current_hand;
+        if !new_state.legal_actions().contains(&action) {
+            let reason = match action {
+                Action::Draw => "Impossible to draw on this hand",
+                Action::Stand => "Impossible to stand now",
+                Action::Double => "Impossible to double on this hand",
+                Action::Insurance => "Impossible to take an insurance",
+                Action::Split => "Impossible to split this hand",
+                Action::Surrender => "Impossible to surrender now",
+            };
+            return Err(BlackjackError::IllegalAction { action, reason });
+        }
+        // The dealer checks for blackjack once insurance and early surrender have been offered
+        if !(action == Action::Insurance
+            || (action == Action::Surrender && new_state.rules.surrender == Surrender::Early))
+        {
+            new_state.peek();
+            if !new_state.continue_game {
+                return Ok(new_state);
+            }
+        }
         match action {
             Action::Draw => {
-                let card = new_state.packet.pick().unwrap();
+                let card = new_state.draw_card()?;
                 // dbg!(" you draw : {} ", &card);
-                new_state.player_cards.add_card(card.clone());
-                new_state.discard.add_card(card.clone());
+                new_state.hands[index].cards.add_card(card);
             }
             Action::Stand => {
-                new_state.continue_game = false;
+                new_state.next_hand()?;
                 // dbg!("You stand");
             }
             Action::Double => {
-                let card = new_state.packet.pick().unwrap();
+                let card = new_state.draw_card()?;
                 // dbg!(" you double and draw : {} ", &card);
-                new_state.player_cards.add_card(card.clone());
-                new_state.discard.add_card(card.clone());
-                new_state.continue_game = false;
-                new_state.double = true;
+                new_state.hands[index].cards.add_card(card);
+                new_state.hands[index].double = true;
+                new_state.next_hand()?;
             }
             Action::Insurance => {
-                if (new_state.croupier_cards.get_card(0).unwrap().unwrap() == &1)
-                    && !new_state.insurance
+                new_state.insurance = true;
+                if new_state.hands[0].is_blackjack() {
+                    // even money, the round is over
+                    new_state.continue_game = false;
+                }
+            }
+            Action::Split => {
+                let second = new_state.hands[index].cards.pick()?;
+                let mut new_hand = PlayerHand {
+                    split: true,
+                    ..PlayerHand::default()
+                };
+                new_hand.cards.add_card(second);
+                new_state.hands[index].split = true;
+                new_state.hands.insert(index + 1, new_hand);
+
+                let card = new_state.draw_card()?;
+                new_state.hands[index].cards.add_card(card);
+                if new_state.hands[index].is_split_aces()
+                    && !new_state.rules.hit_split_aces
+                    && !new_state.can_resplit_aces()
                 {
-                    new_state.insurance = true;
-                    // dbg!("You take insurance");
-                } else {
-                    return Err("Impossible to take an insurance");
+                    new_state.next_hand()?;
                 }
             }
+            Action::Surrender => {
+                new_state.surrender = true;
+                new_state.continue_game = false;
+            }
         }
-        if new_state.player_cards.sum() >= 21 {
-            new_state.continue_game = false;
+        if new_state.continue_game
+            && new_state.current_hand == index
+            && new_state.hands[index].cards.value().total() >= 21
+        {
+            new_state.next_hand()?;
         }
         Ok(new_state)
     }
 
+    // Move to the next hand of the player, the game ends after the last one
+    fn next_hand(&mut self) -> Result<(), BlackjackError> {
+        if self.current_hand + 1 >= self.hands.len() {
+            self.continue_game = false;
+            return Ok(());
+        }
+        self.current_hand += 1;
+        let index = self.current_hand;
+        // The hand only holds the card it was split with
+        let card = self.draw_card()?;
+        self.hands[index].cards.add_card(card);
+        if (self.hands[index].is_split_aces()
+            && !self.rules.hit_split_aces
+            && !self.can_resplit_aces())
+            || self.hands[index].cards.value().total() >= 21
+        {
+            self.next_hand()?;
+        }
+        Ok(())
+    }
+
+    // The dealer draws until the rules tell him to stand
+    pub fn croupier_play(&mut self) -> Result<(), BlackjackError> {
+        if self.croupier_cards.len() == 1 {
+            // no hole card, the dealer gets his second card now
+            let card = self.draw_face_down()?;
+            self.croupier_cards.add_card(card);
+        }
+        // the hole card is turned over
+        if let Some(hole_card) = self.croupier_cards.get_card(1) {
+            self.discard.add_card(*hole_card);
+            self.shoe.reveal(hole_card);
+        }
+
+        if self.surrender
+            || self
+                .hands
+                .iter()
+                .all(|hand| hand.cards.value().is_bust() || hand.is_blackjack())
+        {
+            return Ok(());
+        }
+        while self.rules.croupier_must_draw(&self.croupier_cards.value()) {
+            let card = self.draw_card()?;
+            self.croupier_cards.add_card(card);
+        }
+        Ok(())
+    }
+
     pub fn results(&self, bet: f32) -> f32 {
+        self.hand_results(bet).iter().sum()
+    }
+
+    // Money won by each hand of the player, the insurance goes with the first one
+    pub fn hand_results(&self, bet: f32) -> Vec<f32> {
         let mut total: f32 = 0.0;
+        let croupier = self.croupier_cards.value();
 
-        match (self.insurance, self.croupier_cards.sum()) {
-            (true, 21) => total += bet / 2.0,
-            (true, _) => total -= bet / 2.0,
-            _ => {}
-        }
-        match (
-            self.croupier_cards.sum(),
-            self.player_cards.sum(),
-            self.double,
-        ) {
-            (_, 21, true) => {
-                total += bet * 3.0;
-            }
-            (_, 21, false) => {
-                total += bet * 1.5;
-            }
-            (_, player_point, true) if player_point > 21 => {
-                total -= bet * 2.0;
-            }
-            (_, player_point, false) if player_point > 21 => {
-                total -= bet;
-            }
-            (croupier_point, _, true) if croupier_point > 21 => {
-                total += bet * 2.0;
-            }
-            (croupier_point, _, false) if croupier_point > 21 => {
-                total += bet;
-            }
-            (croupier_point, player_point, true) if croupier_point > player_point => {
-                total -= bet * 2.0;
-            }
-            (croupier_point, player_point, true) if croupier_point < player_point => {
-                total += bet * 2.0;
-            }
-            (croupier_point, player_point, false) if croupier_point > player_point => {
-                total -= bet;
-            }
-            (croupier_point, player_point, false) if croupier_point < player_point => {
-                total += bet;
-            }
-            _ => {}
+        if self.insurance && self.hands[0].is_blackjack() {
+            // even money
+            return vec![bet];
+        }
+        // insurance costs half the bet and pays 2:1 on a dealer blackjack
+        if self.insurance && croupier.is_blackjack() {
+            total += bet;
+        } else if self.insurance {
+            total -= bet / 2.0;
         }
 
-        total
+        if self.surrender {
+            // a late surrender is not accepted when the dealer has blackjack
+            return vec![
+                if croupier.is_blackjack() && self.rules.surrender == Surrender::Late {
+                    total - bet
+                } else {
+                    total - bet / 2.0
+                },
+            ];
+        }
+        if croupier.is_blackjack()
+            && self.rules.dealer_peek
+            && !self.rules.no_hole_card
+            && !self.hands[0].is_blackjack()
+        {
+            // the dealer checked his hole card, only the first bet was on the table
+            return vec![total - bet];
+        }
+        let mut results: Vec<f32> = self
+            .hands
+            .iter()
+            .map(|hand| self.hand_result(hand, bet))
+            .collect();
+        results[0] += total;
+        results
     }
-}
 
-pub fn game(game_state: &mut GameState, bet: f32) -> f32 {
-    let card = game_state.packet.pick().unwrap();
-    println!(" you draw : {} ", &card);
-    game_state.player_cards.add_card(card.clone());
-    game_state.discard.add_card(card.clone());
+    fn hand_result(&self, hand: &PlayerHand, bet: f32) -> f32 {
+        let player = hand.cards.value();
+        let croupier = self.croupier_cards.value();
+        let stake = if hand.double { bet * 2.0 } else { bet };
 
-    let card = game_state.packet.pick().unwrap();
-    println!(" you draw : {} ", &card);
-    game_state.player_cards.add_card(card.clone());
-    game_state.discard.add_card(card.clone());
+        if hand.is_blackjack() && croupier.is_blackjack() {
+            // push
+            0.0
+        } else if player.is_bust() {
+            -stake
+        } else if hand.is_blackjack() {
+            bet * self.rules.blackjack_payout.ratio()
+        } else if croupier.is_blackjack() {
+            // a natural beats any other 21
+            -stake
+        } else if croupier.is_bust() || croupier.total() < player.total() {
+            stake
+        } else if croupier.total() > player.total() {
+            -stake
+        } else {
+            0.0
+        }
+    }
+}
 
-    let card = game_state.packet.pick().unwrap();
-    println!(" croupier draw : {} ", &card);
-    game_state.croupier_cards.add_card(card.clone());
-    game_state.discard.add_card(card.clone());
+// Game in the terminal, `hint` gives its advice before every decision
+pub fn game<'a>(
+    game_state: &mut GameState,
+    bet: f32,
+    mut hint: Option<&mut (dyn Policy + 'a)>,
+) -> Result<f32, BlackjackError> {
+    game_state.bet = bet;
+    game_state.deal()?;
+    for card in game_state.get_player_cards().iterator() {
+        println!(" you draw : {} ", card);
+    }
+    if game_state.hands[0].is_blackjack() && game_state.continue_game {
+        println!("Blackjack ! Le croupier montre un as : insurance pour un paiement à 1:1");
+    }
+    println!(
+        " croupier draw : {} ",
+        game_state.get_croupier_first_card().unwrap()
+    );
 
     while game_state.continue_game {
-        println!("Choisissez une action : draw, stand, double, insurance");
+        if game_state.hands.len() > 1 {
+            println!("Main {} : ", game_state.current_hand + 1);
+        }
+        let actions: Vec<String> = game_state
+            .legal_actions()
+            .iter()
+            .map(|action| action.to_string())
+            .collect();
+        if let Some(policy) = hint.as_deref_mut() {
+            println!(
+                "Conseil ({}) : {}",
+                policy.name(),
+                policy.choose(game_state)
+            );
+        }
+        println!("Choisissez une action : {}", actions.join(", "));
         print!("> ");
-        io::stdout().flush().unwrap(); // Pour s'assurer que l'invite est bien affichée
+        io::stdout().flush()?; // Pour s'assurer que l'invite est bien affichée
 
         let mut input = String::new();
-        io::stdin().read_line(&mut input).unwrap();
+        if io::stdin().read_line(&mut input)? == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
         let input = input.trim().to_lowercase();
 
-        let action = match input.as_str() {
-            "draw" => Action::Draw,
-            "stand" => Action::Stand,
-            "double" => Action::Double,
-            "insurance" => Action::Insurance,
-            _ => {
-                println!("Action invalide. Essayez encore.");
-                continue;
-            }
+        let Ok(action) = input.parse::<Action>() else {
+            println!("Action invalide. Essayez encore.");
+            continue;
         };
 
         match game_state.play(action) {
@@ -211,27 +677,265 @@ pub fn game(game_state: &mut GameState, bet: f32) -> f32 {
                 *game_state = new_state;
                 println!(
                     "État mis à jour. Somme des cartes du joueur : {}",
-                    game_state.player_cards.sum()
+                    game_state.get_player_cards().value()
+                );
+                println!(
+                    "Carte visible du croupier : {} ",
+                    game_state.get_croupier_first_card().unwrap()
                 );
-                println!("Points du croupier : {} ", game_state.croupier_cards.sum());
             }
-            Err(e) => {
+            Err(e @ BlackjackError::IllegalAction { .. }) => {
                 println!("Erreur : {}", e);
             }
+            Err(e) => return Err(e),
         }
     }
 
-    println!(
-        "Fin de la partie. Somme finale des cartes joueur : {}",
-        game_state.player_cards.sum()
-    );
+    for hand in &game_state.hands {
+        println!(
+            "Fin de la partie. Somme finale des cartes joueur : {}",
+            hand.cards.value()
+        );
+    }
+
+    game_state.croupier_play()?;
+    println!("Cartes du croupier : ");
+    for card in game_state.croupier_cards.iterator() {
+        println!(" {} ", card);
+    }
 
-    while game_state.croupier_cards.sum() < 17 {
-        let card = game_state.packet.pick().unwrap();
-        println!(" Croupier drew : {} ", card);
-        game_state.croupier_cards.add_card(card.clone());
-        game_state.discard.add_card(card.clone());
+    Ok(game_state.results(bet))
+}
+
+// Seat played from the terminal, the decisions are asked to the player
+pub struct HumanPolicy;
+
+impl Policy for HumanPolicy {
+    fn name(&self) -> String {
+        "human".to_string()
     }
 
-    game_state.results(bet)
+    fn choose(&mut self, game_state: &GameState) -> Action {
+        let legal = game_state.legal_actions();
+        let actions: Vec<String> = legal.iter().map(|action| action.to_string()).collect();
+        loop {
+            println!(
+                "Vos cartes : {} ({}), carte visible du croupier : {}",
+                game_state
+                    .get_player_cards()
+                    .iterator()
+                    .iter()
+                    .map(|card| card.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                game_state.get_player_cards().value(),
+                game_state.get_croupier_first_card().unwrap()
+            );
+            println!("Choisissez une action : {}", actions.join(", "));
+            print!("> ");
+            let mut input = String::new();
+            // Nothing more to read : the player stands
+            if io::stdout().flush().is_err()
+                || !matches!(io::stdin().read_line(&mut input), Ok(n) if n > 0)
+            {
+                return Action::Stand;
+            }
+            match input.trim().to_lowercase().parse::<Action>() {
+                Ok(action) if legal.contains(&action) => return action,
+                _ => println!("Action invalide. Essayez encore."),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Table where the player holds `ranks` against the up card of the dealer
+    fn table(rules: RuleSet, ranks: &[Rank], up: Rank) -> GameState {
+        let mut game_state = GameState::with_rules(rules);
+        for &rank in ranks {
+            game_state.hands[0]
+                .cards
+                .add_card(Card::new(rank, Suit::Pique));
+        }
+        game_state
+            .croupier_cards
+            .add_card(Card::new(up, Suit::Coeur));
+        game_state
+    }
+
+    #[test]
+    fn split_pair_is_played_as_two_hands() {
+        let mut game_state = table(RuleSet::default(), &[Rank::Eight, Rank::Eight], Rank::Six);
+        assert!(game_state.can_split());
+        let mut game_state = game_state.play(Action::Split).unwrap();
+        assert_eq!(game_state.hands.len(), 2);
+        assert_eq!(game_state.current_hand, 0);
+        assert_eq!(game_state.hands[0].cards.len(), 2);
+        // the second hand waits for its card
+        assert_eq!(game_state.hands[1].cards.len(), 1);
+        assert!(game_state.hands.iter().all(|hand| hand.split));
+
+        let game_state = game_state.play(Action::Stand).unwrap();
+        assert_eq!(game_state.current_hand, 1);
+        assert_eq!(game_state.hands[1].cards.len(), 2);
+
+        let no_split = RuleSet {
+            max_splits: 0,
+            ..RuleSet::default()
+        };
+        assert!(!table(no_split, &[Rank::Eight, Rank::Eight], Rank::Six).can_split());
+    }
+
+    #[test]
+    fn split_aces_receive_one_card_each() {
+        let mut game_state = table(RuleSet::default(), &[Rank::Ace, Rank::Ace], Rank::Six);
+        let game_state = game_state.play(Action::Split).unwrap();
+        assert!(!game_state.continue_game);
+        for hand in &game_state.hands {
+            assert_eq!(hand.cards.len(), 2);
+            // an ace and a ten after a split is only 21
+            assert!(!hand.is_blackjack());
+        }
+    }
+
+    #[test]
+    fn split_aces_are_split_again_when_an_ace_is_dealt() {
+        let rules = RuleSet {
+            resplit_aces: true,
+            hit_split_aces: false,
+            ..RuleSet::default()
+        };
+        // first seed whose shoe deals an ace on the first split ace
+        let mut game_state = (0..)
+            .map(|seed| {
+                let mut game_state = table(rules.clone(), &[Rank::Ace, Rank::Ace], Rank::Six);
+                game_state.shoe =
+                    Shoe::seeded(rules.decks, DEFAULT_PENETRATION, DEFAULT_BURN_CARDS, seed);
+                game_state.play(Action::Split).unwrap()
+            })
+            .find(|game_state| {
+                game_state.hands[0]
+                    .cards
+                    .get_card(1)
+                    .is_some_and(|card| card.rank == Rank::Ace)
+            })
+            .unwrap();
+        // the hand waits on the table, it can only be split again
+        assert!(game_state.continue_game);
+        assert_eq!(game_state.current_hand, 0);
+        assert_eq!(game_state.legal_actions(), vec![Action::Split]);
+        assert!(game_state.play(Action::Draw).is_err());
+
+        let game_state = game_state.play(Action::Split).unwrap();
+        assert_eq!(game_state.hands.len(), 3);
+        assert!(game_state.hands.iter().all(|hand| hand.split));
+    }
+
+    #[test]
+    fn surrender_gives_back_half_the_bet() {
+        for (surrender, hole, result) in [
+            (Surrender::Late, Rank::Seven, -0.5),
+            // a late surrender does not save the bet from a dealer blackjack, an early one does
+            (Surrender::Late, Rank::Ace, -1.0),
+            (Surrender::Early, Rank::Ace, -0.5),
+        ] {
+            let rules = RuleSet {
+                surrender,
+                ..RuleSet::default()
+            };
+            let mut game_state = table(rules, &[Rank::Ten, Rank::Six], Rank::Ten);
+            game_state
+                .croupier_cards
+                .add_card(Card::new(hole, Suit::Coeur));
+            let game_state = game_state.play(Action::Surrender).unwrap();
+            assert!(!game_state.continue_game);
+            assert_eq!(game_state.results(1.0), result);
+        }
+        let mut game_state = table(RuleSet::default(), &[Rank::Ten, Rank::Six], Rank::Ten);
+        assert!(game_state.play(Action::Surrender).is_err());
+    }
+
+    #[test]
+    fn dealer_peeks_before_the_player_plays() {
+        // the double is refused by the peek, only the first bet is lost
+        let mut game_state = table(RuleSet::default(), &[Rank::Six, Rank::Five], Rank::Ten);
+        game_state
+            .croupier_cards
+            .add_card(Card::new(Rank::Ace, Suit::Coeur));
+        let game_state = game_state.play(Action::Double).unwrap();
+        assert!(!game_state.continue_game);
+        assert_eq!(game_state.hands[0].cards.len(), 2);
+        assert_eq!(game_state.results(1.0), -1.0);
+    }
+
+    #[test]
+    fn insurance_pays_two_to_one_on_a_dealer_blackjack() {
+        let mut game_state = table(RuleSet::default(), &[Rank::Ten, Rank::Nine], Rank::Ace);
+        game_state
+            .croupier_cards
+            .add_card(Card::new(Rank::King, Suit::Coeur));
+        assert!(game_state.can_insure());
+        let mut game_state = game_state.play(Action::Insurance).unwrap();
+        assert!(game_state.continue_game);
+        let game_state = game_state.play(Action::Stand).unwrap();
+        // the insurance wins what the bet loses
+        assert_eq!(game_state.results(1.0), 0.0);
+
+        // even money on a blackjack, whatever the hole card
+        let mut game_state = table(RuleSet::default(), &[Rank::Ace, Rank::King], Rank::Ace);
+        game_state
+            .croupier_cards
+            .add_card(Card::new(Rank::Seven, Suit::Coeur));
+        let game_state = game_state.play(Action::Insurance).unwrap();
+        assert!(!game_state.continue_game);
+        assert_eq!(game_state.results(1.0), 1.0);
+    }
+
+    #[test]
+    fn legal_actions_follow_the_rules_and_the_bankroll() {
+        let rules = RuleSet {
+            surrender: Surrender::Late,
+            ..RuleSet::default()
+        };
+        let mut game_state = table(rules, &[Rank::Eight, Rank::Eight], Rank::Ace);
+        assert_eq!(game_state.legal_actions(), Action::ALL.to_vec());
+        // half a bet left : insurance but no double nor split
+        game_state.set_bankroll(1.5, 1.0);
+        assert_eq!(
+            game_state.legal_actions(),
+            vec![
+                Action::Draw,
+                Action::Stand,
+                Action::Insurance,
+                Action::Surrender
+            ]
+        );
+
+        let blackjack = table(RuleSet::default(), &[Rank::Ace, Rank::King], Rank::Six);
+        assert_eq!(blackjack.legal_actions(), vec![Action::Stand]);
+    }
+
+    #[test]
+    fn refused_actions_are_typed_errors() {
+        let mut game_state = table(RuleSet::default(), &[Rank::Ten, Rank::Six], Rank::Six);
+        let error = game_state.play(Action::Split).err().unwrap();
+        assert!(matches!(
+            error,
+            BlackjackError::IllegalAction {
+                action: Action::Split,
+                ..
+            }
+        ));
+        assert!(error.to_string().starts_with("Illegal action"));
+        // nothing can be played once the game is over
+        let mut game_state = game_state.play(Action::Stand).unwrap();
+        assert!(game_state.play(Action::Stand).is_err());
+        assert!(matches!(
+            PackOfCards::new().pick(),
+            Err(BlackjackError::EmptyShoe)
+        ));
+    }
 }
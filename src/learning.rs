@@ -0,0 +1,529 @@
+use crate::env::Environment;
+use crate::error::BlackjackError;
+use crate::game::*;
+use crate::schedule::Behaviour;
+use crate::training::*;
+use dashmap::DashMap;
+use rand::{Rng, RngCore};
+use std::collections::HashSet;
+
+// Every learner reads the episodes of QTable::play_episode, so they can be compared on the same games
+pub trait Learner: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // Values used to choose the action in a state
+    fn values(&self, table: &QTable, state: &State) -> Option<Vec<f32>> {
+        table.states.get(state).map(|values| values.clone())
+    }
+
+    fn learn(
+        &self,
+        table: &QTable,
+        episode: &[Transition],
+        behaviour: &Behaviour,
+        rng: &mut dyn RngCore,
+    ) -> Result<(), BlackjackError>;
+
+    // Called once the training is over
+    fn finish(&self, _table: &QTable) {}
+
+    // Values the learner keeps besides the table and their updates, written in the checkpoints
+    fn saved_values(&self) -> Vec<(State, Vec<f32>)> {
+        Vec::new()
+    }
+
+    fn saved_visits(&self) -> Vec<(State, Vec<u64>)> {
+        Vec::new()
+    }
+
+    fn restore_values(&self, _values: Vec<(State, Vec<f32>)>, _visits: Vec<(State, Vec<u64>)>) {}
+}
+
+// Play one round of `env` with the exploration of the table on the learner's values and learn from it
+pub fn train_episode<E: Environment<Observation = State>>(
+    learner: &dyn Learner,
+    table: &QTable,
+    env: &mut E,
+    behaviour: &Behaviour,
+    rng: &mut dyn RngCore,
+) -> Result<f32, BlackjackError> {
+    let (episode, reward) = table.play_episode(env, |state| {
+        let values = learner
+            .values(table, state)
+            .ok_or_else(|| BlackjackError::MissingState(state.clone()))?;
+        behaviour.choose(state, &values, rng)
+    })?;
+    learner.learn(table, &episode, behaviour, rng)?;
+    table.record_game();
+    Ok(reward)
+}
+
+fn check_reward(transition: &Transition) -> Result<(), BlackjackError> {
+    if transition.reward.is_finite() {
+        Ok(())
+    } else {
+        Err(BlackjackError::InvalidReward(transition.reward))
+    }
+}
+
+fn value(table: &QTable, state: &State, action: Action) -> Result<f32, BlackjackError> {
+    table
+        .states
+        .get(state)
+        .map(|values| values[action.into_index()])
+        .ok_or_else(|| BlackjackError::MissingState(state.clone()))
+}
+
+// Off-policy : bootstrap on the best legal action of the next state
+pub struct QLearning;
+
+impl Learner for QLearning {
+    fn name(&self) -> &'static str {
+        "q-learning"
+    }
+
+    fn learn(
+        &self,
+        table: &QTable,
+        episode: &[Transition],
+        _behaviour: &Behaviour,
+        _rng: &mut dyn RngCore,
+    ) -> Result<(), BlackjackError> {
+        table.learn(episode)
+    }
+}
+
+// On-policy : bootstrap on the action that was really played in the next state
+pub struct Sarsa;
+
+impl Learner for Sarsa {
+    fn name(&self) -> &'static str {
+        "sarsa"
+    }
+
+    fn learn(
+        &self,
+        table: &QTable,
+        episode: &[Transition],
+        _behaviour: &Behaviour,
+        _rng: &mut dyn RngCore,
+    ) -> Result<(), BlackjackError> {
+        for transition in episode.iter().rev() {
+            check_reward(transition)?;
+            let target = match (&transition.next_state, transition.next_action) {
+                (Some(next_state), Some(next_action)) => {
+                    transition.reward
+                        + table.metadata.gamma * value(table, next_state, next_action)?
+                }
+                // every state but the last of a hand is followed by the action played in it
+                (Some(next_state), None) => {
+                    return Err(BlackjackError::NoLegalAction(next_state.clone()));
+                }
+                (None, _) => transition.reward,
+            };
+            table.update_toward(&transition.state, transition.action, target)?;
+        }
+        Ok(())
+    }
+}
+
+// Bootstrap on the mean value of the next state under the epsilon-greedy policy
+pub struct ExpectedSarsa;
+
+impl Learner for ExpectedSarsa {
+    fn name(&self) -> &'static str {
+        "expected-sarsa"
+    }
+
+    fn learn(
+        &self,
+        table: &QTable,
+        episode: &[Transition],
+        behaviour: &Behaviour,
+        _rng: &mut dyn RngCore,
+    ) -> Result<(), BlackjackError> {
+        for transition in episode.iter().rev() {
+            check_reward(transition)?;
+            let target = match &transition.next_state {
+                Some(next_state) => {
+                    let values = table
+                        .states
+                        .get(next_state)
+                        .ok_or_else(|| BlackjackError::MissingState(next_state.clone()))?
+                        .clone();
+                    transition.reward
+                        + table.metadata.gamma * expected_value(next_state, &values, behaviour)
+                }
+                None => transition.reward,
+            };
+            table.update_toward(&transition.state, transition.action, target)?;
+        }
+        Ok(())
+    }
+}
+
+// Mean of the values of the legal actions, weighted by the probability that the exploration plays them
+fn expected_value(state: &State, values: &[f32], behaviour: &Behaviour) -> f32 {
+    state
+        .legal_actions()
+        .iter()
+        .zip(behaviour.probabilities(state, values))
+        .map(|(action, p)| p * values[action.into_index()])
+        .sum()
+}
+
+// No bootstrap, each decision moves toward the money won from there, once per game with first_visit
+pub struct MonteCarlo {
+    pub first_visit: bool,
+}
+
+impl Learner for MonteCarlo {
+    fn name(&self) -> &'static str {
+        if self.first_visit {
+            "monte-carlo-first-visit"
+        } else {
+            "monte-carlo-every-visit"
+        }
+    }
+
+    fn learn(
+        &self,
+        table: &QTable,
+        episode: &[Transition],
+        _behaviour: &Behaviour,
+        _rng: &mut dyn RngCore,
+    ) -> Result<(), BlackjackError> {
+        let mut returns = vec![0.0; episode.len()];
+        let mut g = 0.0;
+        for (index, transition) in episode.iter().enumerate().rev() {
+            check_reward(transition)?;
+            // each hand of a split ends its own chain of decisions
+            if transition.next_state.is_none() {
+                g = 0.0;
+            }
+            g = transition.reward + table.metadata.gamma * g;
+            returns[index] = g;
+        }
+
+        let mut seen = HashSet::new();
+        for (transition, g) in episode.iter().zip(returns) {
+            if self.first_visit && !seen.insert((&transition.state, transition.action)) {
+                continue;
+            }
+            table.update_toward(&transition.state, transition.action, g)?;
+        }
+        Ok(())
+    }
+}
+
+// Two tables, each update evaluates the best action of one with the other to remove the max bias.
+// The actions are chosen on their sum and the trained table gets their mean at the end.
+#[derive(Default)]
+pub struct DoubleQLearning {
+    second: DashMap<State, Vec<f32>>,
+    second_visits: DashMap<State, Vec<u64>>,
+}
+
+impl DoubleQLearning {
+    pub fn new() -> Self {
+        DoubleQLearning::default()
+    }
+
+    fn second_values(&self, table: &QTable, state: &State) -> Vec<f32> {
+        self.second
+            .entry(state.clone())
+            .or_insert_with(|| vec![table.metadata.initial_value; Action::ALL.len()])
+            .clone()
+    }
+
+    // Best legal action of `state` in `chooser`, valued by `evaluator`
+    fn cross_value(state: &State, chooser: &[f32], evaluator: &[f32]) -> f32 {
+        state
+            .legal_actions()
+            .iter()
+            .filter(|action| !chooser[action.into_index()].is_nan())
+            .max_by(|a, b| {
+                chooser[a.into_index()]
+                    .partial_cmp(&chooser[b.into_index()])
+                    .unwrap()
+            })
+            .map_or(0.0, |action| evaluator[action.into_index()])
+    }
+}
+
+impl Learner for DoubleQLearning {
+    fn name(&self) -> &'static str {
+        "double-q-learning"
+    }
+
+    fn values(&self, table: &QTable, state: &State) -> Option<Vec<f32>> {
+        let first = table.states.get(state)?.clone();
+        let second = self.second_values(table, state);
+        Some(first.iter().zip(second).map(|(a, b)| a + b).collect())
+    }
+
+    fn learn(
+        &self,
+        table: &QTable,
+        episode: &[Transition],
+        _behaviour: &Behaviour,
+        rng: &mut dyn RngCore,
+    ) -> Result<(), BlackjackError> {
+        let gamma = table.metadata.gamma;
+        for transition in episode.iter().rev() {
+            check_reward(transition)?;
+            let index = transition.action.into_index();
+            let update_first = rng.gen_bool(0.5);
+            let bootstrap = match &transition.next_state {
+                Some(next_state) => {
+                    let first = table
+                        .states
+                        .get(next_state)
+                        .ok_or_else(|| BlackjackError::MissingState(next_state.clone()))?
+                        .clone();
+                    let second = self.second_values(table, next_state);
+                    if update_first {
+                        Self::cross_value(next_state, &first, &second)
+                    } else {
+                        Self::cross_value(next_state, &second, &first)
+                    }
+                }
+                None => 0.0,
+            };
+            let target = transition.reward + gamma * bootstrap;
+
+            if update_first {
+                table.update_toward(&transition.state, transition.action, target)?;
+            } else {
+                let visits = {
+                    let mut visits = self
+                        .second_visits
+                        .entry(transition.state.clone())
+                        .or_insert_with(|| vec![0; Action::ALL.len()]);
+                    visits[index] += 1;
+                    visits[index]
+                };
+                let alpha = table.metadata.learning_rate.alpha(visits, table.games());
+                let mut values = self
+                    .second
+                    .entry(transition.state.clone())
+                    .or_insert_with(|| vec![table.metadata.initial_value; Action::ALL.len()]);
+                values[index] += alpha * (target - values[index]);
+            }
+        }
+        Ok(())
+    }
+
+    fn saved_values(&self) -> Vec<(State, Vec<f32>)> {
+        self.second
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect()
+    }
+
+    fn saved_visits(&self) -> Vec<(State, Vec<u64>)> {
+        self.second_visits
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect()
+    }
+
+    fn restore_values(&self, values: Vec<(State, Vec<f32>)>, visits: Vec<(State, Vec<u64>)>) {
+        self.second.clear();
+        for (state, values) in values {
+            self.second.insert(state, values);
+        }
+        self.second_visits.clear();
+        for (state, visits) in visits {
+            self.second_visits.insert(state, visits);
+        }
+    }
+
+    fn finish(&self, table: &QTable) {
+        for mut entry in table.states.iter_mut() {
+            let second = self.second_values(table, entry.key());
+            for (value, other) in entry.value_mut().iter_mut().zip(second) {
+                *value = (*value + other) / 2.0;
+            }
+        }
+    }
+}
+
+// Learner from its name, as written in the table metadata
+pub fn learner_from_name(name: &str) -> Option<Box<dyn Learner>> {
+    match name {
+        "q-learning" => Some(Box::new(QLearning)),
+        "sarsa" => Some(Box::new(Sarsa)),
+        "expected-sarsa" => Some(Box::new(ExpectedSarsa)),
+        "monte-carlo-first-visit" => Some(Box::new(MonteCarlo { first_visit: true })),
+        "monte-carlo-every-visit" => Some(Box::new(MonteCarlo { first_visit: false })),
+        "double-q-learning" => Some(Box::new(DoubleQLearning::new())),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::card::{Card, Rank, Suit};
+    use crate::rules::RuleSet;
+    use crate::schedule::{EpsilonSchedule, Exploration, LearningRate};
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    // Hand of the player against a dealer ten
+    fn state(ranks: &[Rank]) -> State {
+        let cards: Vec<Card> = ranks
+            .iter()
+            .map(|&rank| Card::new(rank, Suit::Pique))
+            .collect();
+        let up_card = Card::new(Rank::Ten, Suit::Coeur);
+        State::from(&GameState::from_cards(RuleSet::default(), &cards, up_card).unwrap())
+    }
+
+    fn transition(state: &State, action: Action, reward: f32, next: Option<&State>) -> Transition {
+        Transition {
+            state: state.clone(),
+            action,
+            reward,
+            next_state: next.cloned(),
+            next_action: None,
+        }
+    }
+
+    fn q(table: &QTable, state: &State, action: Action) -> f32 {
+        table.states.get(state).unwrap()[action.into_index()]
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    // Hit on 12, stand on 17 and win, with draw on 17 valued 0.5 so each learner bootstraps differently
+    fn hit_then_stand() -> (QTable, State, State, Vec<Transition>) {
+        let (s12, s17) = (
+            state(&[Rank::Ten, Rank::Two]),
+            state(&[Rank::Ten, Rank::Two, Rank::Five]),
+        );
+        assert_eq!(s17.legal_actions(), [Action::Draw, Action::Stand]);
+        let table = QTable::new();
+        table.add_state(s12.clone());
+        table.add_state(s17.clone());
+        table.states.get_mut(&s17).unwrap()[Action::Draw.into_index()] = 0.5;
+        let mut first = transition(&s12, Action::Draw, 0.0, Some(&s17));
+        first.next_action = Some(Action::Stand);
+        let episode = vec![first, transition(&s17, Action::Stand, 1.0, None)];
+        (table, s12, s17, episode)
+    }
+
+    fn behaviour(table: &QTable) -> Behaviour<'_> {
+        Behaviour {
+            table,
+            episode: 0,
+            total: 1,
+        }
+    }
+
+    #[test]
+    fn td_learners_bootstrap_on_their_own_value() {
+        let mut rng = StdRng::seed_from_u64(0);
+
+        // Q-learning : the best action of 17, draw at 0.5
+        let (table, s12, s17, episode) = hit_then_stand();
+        QLearning
+            .learn(&table, &episode, &behaviour(&table), &mut rng)
+            .unwrap();
+        assert!(close(q(&table, &s17, Action::Stand), 0.1));
+        assert!(close(q(&table, &s12, Action::Draw), 0.05));
+
+        // SARSA : the stand really played, 0.1 once learned
+        let (table, s12, _, episode) = hit_then_stand();
+        Sarsa
+            .learn(&table, &episode, &behaviour(&table), &mut rng)
+            .unwrap();
+        assert!(close(q(&table, &s12, Action::Draw), 0.01));
+        // a next state without the action played in it is a broken episode
+        let (table, _, s17, mut episode) = hit_then_stand();
+        episode[0].next_action = None;
+        assert!(matches!(
+            Sarsa.learn(&table, &episode, &behaviour(&table), &mut rng),
+            Err(BlackjackError::NoLegalAction(state)) if state == s17
+        ));
+
+        // Expected SARSA : epsilon 0.2 plays draw 0.9 of the time and stand 0.1
+        let (mut table, s12, _, episode) = hit_then_stand();
+        table.metadata.exploration = Exploration::EpsilonGreedy(EpsilonSchedule::Constant(0.2));
+        ExpectedSarsa
+            .learn(&table, &episode, &behaviour(&table), &mut rng)
+            .unwrap();
+        assert!(close(
+            q(&table, &s12, Action::Draw),
+            0.1 * (0.9 * 0.5 + 0.1 * 0.1)
+        ));
+    }
+
+    #[test]
+    fn first_visit_only_counts_a_repeated_state_once() {
+        // the two hands of a split stand on the same 12, one wins and one loses
+        let s12 = state(&[Rank::Ten, Rank::Two]);
+        let episode = vec![
+            transition(&s12, Action::Stand, 1.0, None),
+            transition(&s12, Action::Stand, -1.0, None),
+        ];
+        let learn = |first_visit| {
+            let table = QTable::new();
+            table.add_state(s12.clone());
+            MonteCarlo { first_visit }
+                .learn(
+                    &table,
+                    &episode,
+                    &behaviour(&table),
+                    &mut StdRng::seed_from_u64(0),
+                )
+                .unwrap();
+            q(&table, &s12, Action::Stand)
+        };
+        assert!(close(learn(true), 0.1));
+        assert!(close(learn(false), 0.1 + 0.1 * (-1.0 - 0.1)));
+    }
+
+    #[test]
+    fn double_q_evaluates_with_the_other_table() {
+        let (table, s12, s17, episode) = hit_then_stand();
+        let learner = DoubleQLearning::new();
+        let mut second = vec![0.0; Action::ALL.len()];
+        second[Action::Draw.into_index()] = -0.3;
+        second[Action::Stand.into_index()] = 0.8;
+        learner.restore_values(vec![(s17.clone(), second)], Vec::new());
+        table.states.get_mut(&s17).unwrap()[Action::Stand.into_index()] = 0.2;
+        let mut rng = StdRng::seed_from_u64(0);
+        learner
+            .learn(&table, &episode[..1], &behaviour(&table), &mut rng)
+            .unwrap();
+        // the first table picks draw valued -0.3 by the second, the second picks stand valued 0.2
+        let first = q(&table, &s12, Action::Draw);
+        let second = learner.second_values(&table, &s12)[Action::Draw.into_index()];
+        assert!((close(first, -0.03) && second == 0.0) || (first == 0.0 && close(second, 0.02)));
+
+        // with 1/N each table averages its own targets
+        let mut table = QTable::new();
+        table.metadata.learning_rate = LearningRate::Visits;
+        table.add_state(s12.clone());
+        let learner = DoubleQLearning::new();
+        let win = [transition(&s12, Action::Stand, 1.0, None)];
+        for _ in 0..20 {
+            learner
+                .learn(&table, &win, &behaviour(&table), &mut rng)
+                .unwrap();
+        }
+        assert_eq!(q(&table, &s12, Action::Stand), 1.0);
+        assert_eq!(
+            learner.second_values(&table, &s12)[Action::Stand.into_index()],
+            1.0
+        );
+        let visits = table.state_visits(&s12);
+        let second_visits: u64 = learner.saved_visits().iter().flat_map(|(_, v)| v).sum();
+        assert!(visits > 0 && second_visits > 0);
+        assert_eq!(visits + second_visits, 20);
+    }
+}
@@ -1,24 +1,122 @@
 pub mod card;
+pub mod checkpoint;
+pub mod cli;
+pub mod config;
+pub mod counting;
+pub mod env;
+pub mod error;
+pub mod evaluation;
 pub mod game;
+pub mod learning;
+pub mod parallel;
+pub mod risk;
+pub mod rules;
+pub mod schedule;
+pub mod session;
+pub mod shoe;
+pub mod solver;
+pub mod table;
 pub mod training;
+use crate::checkpoint::Checkpointer;
+use crate::cli::*;
+use crate::error::BlackjackError;
+use crate::evaluation::*;
+use crate::game::{Action, GameState, HumanPolicy, game};
+use crate::learning::learner_from_name;
+use crate::parallel::*;
+use crate::risk::analyse;
+use crate::rules::RuleSet;
+use crate::session::simulate;
+use crate::solver::{Solver, StrategyChart, solve};
+use crate::table::Table;
 use crate::training::QTable;
-use game::*;
+use std::io;
+use std::io::Write;
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::thread;
 use std::time::Duration;
 
-const NB_ITERATIONS: u64 = 1_000_000; // Number of iterations for training
-const NUM_THREADS: usize = 20;
 const SPINNER_FRAMES: &[&str] = &["|", "/", "-", "\\"];
+const BENCH_EPISODES: u64 = 200_000; // Games played for each thread count of the benchmark
 
 fn main() {
-    let locker = Arc::new(Mutex::new(QTable::new()));
-    let mut handles = vec![];
-    let progress = Arc::new(Mutex::new(vec![(0u8, 0f32, 0usize); NUM_THREADS]));
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let command = match cli::parse(&args) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("Error: {}\n\n{}", e, usage(None));
+            std::process::exit(2);
+        }
+    };
+
+    let result = match command {
+        Command::Train(options) => run_training(options),
+        Command::Play(options) => run_play(options),
+        Command::Eval(options) => run_evaluation(options),
+        Command::Advise(options) => run_advise(options),
+        Command::Export(options) => run_export(options),
+        Command::Session(options) => run_session(options),
+        Command::Risk(options) => run_risk(options),
+        Command::Table(options) => run_table(options),
+        Command::Bench { max_threads } => {
+            let max_threads = max_threads
+                .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+            run_benchmark(max_threads);
+            Ok(())
+        }
+        Command::Help(command) => {
+            println!("{}", usage(command.as_deref()));
+            Ok(())
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run_training(options: TrainOptions) -> Result<(), BlackjackError> {
+    let (table, learner, episodes, threads, checkpoint) = match &options.resume {
+        // The configuration, the games played and the generators come from the checkpoint
+        Some(path) => {
+            let (table, run) = QTable::load_checkpoint(path)?;
+            let run = run.ok_or_else(|| {
+                BlackjackError::InvalidArgument(format!("{} is not a checkpoint", path))
+            })?;
+            let learner = learner_from_name(&table.metadata.algorithm).ok_or_else(|| {
+                BlackjackError::InvalidArgument(format!(
+                    "unknown learner '{}' in {}",
+                    table.metadata.algorithm, path
+                ))
+            })?;
+            learner.restore_values(run.learner, run.learner_visits);
+            println!(
+                "Resuming {} at game {} of {} for each thread",
+                path, run.threads[0].episode, run.episodes
+            );
+            (table, learner, run.episodes, run.threads, run.checkpoint)
+        }
+        None => {
+            let config = options.config;
+            let mut table = config.table();
+            // Written in the table so that the run can be replayed
+            table.metadata.seed = config.seed.unwrap_or_else(rand::random);
+            let threads = thread_states(&table.metadata.rules, table.metadata.seed, config.threads);
+            let learner = learner_from_name(&config.learner).unwrap();
+            (table, learner, config.episodes, threads, 0)
+        }
+    };
+    println!("Seed: {}", table.metadata.seed);
+    let checkpointer = options
+        .checkpoints
+        .enabled()
+        .then(|| Checkpointer::new(options.checkpoints.clone(), table.games(), checkpoint));
+    let nb_threads = threads.len();
+    let progress: Progress = Arc::new(Mutex::new(vec![(0u8, 0f32, 0usize); nb_threads]));
 
     // Affichage initial
-    for _ in 0..NUM_THREADS {
+    for _ in 0..nb_threads {
         println!("Thread progress: ");
     }
 
@@ -30,7 +128,7 @@ fn main() {
                 {
                     let mut progress = progress.lock().unwrap();
                     // Remonter le curseur
-                    print!("\x1B[{}A", NUM_THREADS);
+                    print!("\x1B[{}A", nb_threads);
                     for (i, (percent, avg_reward, spinner_idx)) in progress.iter_mut().enumerate() {
                         // Update spinner
                         *spinner_idx = (*spinner_idx + 1) % SPINNER_FRAMES.len();
@@ -46,61 +144,363 @@ fn main() {
         });
     }
 
-    for thread_id in 0..NUM_THREADS {
-        let locker = Arc::clone(&locker);
-        let progress = Arc::clone(&progress);
-        let handle = thread::spawn(move || {
-            training_for_thread(NB_ITERATIONS, &locker, &progress, thread_id);
-        });
-        handles.push(handle);
+    train_from(
+        &table,
+        learner.as_ref(),
+        episodes,
+        threads,
+        checkpointer.as_ref(),
+        Some(&progress),
+    );
+
+    // Save the QTable to a file, the games played are counted by the table
+    table.save(&options.output)?;
+    println!("success, table saved to {}", options.output);
+    Ok(())
+}
+
+// Where a policy comes from : a saved table or the name of a built-in one
+enum PolicySource {
+    Table(QTable),
+    BuiltIn(String),
+}
+
+impl PolicySource {
+    fn from(name: &str) -> Result<PolicySource, BlackjackError> {
+        if POLICIES.contains(&name) {
+            Ok(PolicySource::BuiltIn(name.to_string()))
+        } else {
+            Ok(PolicySource::Table(QTable::load(name)?))
+        }
     }
 
-    for handle in handles {
-        handle.join().unwrap();
+    // Rules the policy is played with, a table keeps the ones it was trained on
+    fn rules(&self, rules: &RuleSet) -> RuleSet {
+        match self {
+            PolicySource::Table(table) => table.metadata.rules.clone(),
+            PolicySource::BuiltIn(_) => rules.clone(),
+        }
     }
 
-    // Save the QTable to a file
-    let q = locker.lock().unwrap();
-    let res = q.save("qtable.json");
-    match res {
-        Ok(_) => {
-            print!("success");
+    fn policy(&self, rules: &RuleSet, seed: u64) -> Box<dyn Policy + '_> {
+        match self {
+            PolicySource::Table(table) => Box::new(GreedyPolicy { table }),
+            PolicySource::BuiltIn(name) => match name.as_str() {
+                "basic" => Box::new(ChartPolicy {
+                    chart: solve_chart(rules),
+                }),
+                "mimic-dealer" => Box::new(MimicDealer),
+                "never-bust" => Box::new(NeverBust),
+                _ => Box::new(RandomPolicy::new(seed)),
+            },
         }
-        Err(e) => {
-            print!("failed, {:?}", e);
+    }
+}
+
+fn run_play(options: PlayOptions) -> Result<(), BlackjackError> {
+    let source = options
+        .hint
+        .as_deref()
+        .map(PolicySource::from)
+        .transpose()?;
+    let mut hint = source
+        .as_ref()
+        .map(|source| source.policy(&options.rules, rand::random()));
+    let mut game_state = GameState::with_rules(options.rules.clone());
+    let mut bankroll = options.bankroll;
+    let mut total = 0.0;
+    loop {
+        game_state.new_round();
+        if let Some(money) = bankroll {
+            if money < options.bet {
+                println!("Plus assez d'argent pour miser {}", options.bet);
+                break;
+            }
+            game_state.set_bankroll(money, options.bet);
+        }
+        let result = game(&mut game_state, options.bet, hint.as_deref_mut())?;
+        total += result;
+        bankroll = bankroll.map(|money| money + result);
+        println!("Résultat : {:+}, total : {:+}", result, total);
+        if let Some(money) = bankroll {
+            println!("Bankroll : {}", money);
+        }
+
+        print!("Nouvelle partie ? (o/n) ");
+        io::stdout().flush()?;
+        let mut input = String::new();
+        // Nothing more to read : end of the session
+        if io::stdin().read_line(&mut input)? == 0
+            || !matches!(input.trim().to_lowercase().as_str(), "" | "o" | "oui" | "y")
+        {
+            break;
         }
     }
+    Ok(())
 }
 
-pub fn training_for_thread(
-    nb_iterations: u64,
-    locker: &Arc<Mutex<QTable>>,
-    progress: &Arc<Mutex<Vec<(u8, f32, usize)>>>,
-    thread_id: usize,
-) {
-    let mut avg_reward = 0.0;
-    for per in 0..nb_iterations {
-        let percentage = nb_iterations as f32 * 0.05;
-
-        let epsilon = if per < percentage as u64 {
-            1.0 - (per as f32 / nb_iterations as f32)
-        } else {
-            0.02
-        };
-        let mut q = locker.lock().unwrap();
-        let mut game_state = GameState::new();
-        let reward = q.trainnig_q(&mut game_state, 1.0, epsilon);
-
-        // Mise à jour du pourcentage toutes les 100 itérations
-        if per % 1000 == 0 || per == nb_iterations - 1 {
-            let percent = ((per + 1) * 100 / nb_iterations) as u8;
-            let mut p = progress.lock().unwrap();
-            p[thread_id].0 = percent;
-
-            p[thread_id].1 = avg_reward;
-            avg_reward = 0.0;
-        } else {
-            avg_reward += reward;
+// The solver takes a while, on stderr so that a chart printed on stdout stays whole
+fn solve_chart(rules: &RuleSet) -> StrategyChart {
+    eprintln!("Solving the basic strategy of the rules...");
+    solve(rules)
+}
+
+// Chart read from `path` when it was solved for `rules`, else solved and written there
+fn cached_chart(rules: &RuleSet, path: &str) -> Result<StrategyChart, BlackjackError> {
+    if let Ok(text) = std::fs::read_to_string(path) {
+        let chart: StrategyChart = serde_json::from_str(&text)?;
+        if chart.rules == *rules {
+            return Ok(chart);
         }
+        println!(
+            "{} holds the chart of other rules, it is solved again",
+            path
+        );
+    }
+    let chart = solve_chart(rules);
+    std::fs::write(path, serde_json::to_string_pretty(&chart)?)?;
+    println!("Chart written to {}", path);
+    Ok(chart)
+}
+
+fn run_evaluation(options: EvalOptions) -> Result<(), BlackjackError> {
+    let source = PolicySource::from(&options.policy)?;
+    let rules = source.rules(&options.rules);
+    let mut policy = match &options.chart {
+        Some(path) => Box::new(ChartPolicy {
+            chart: cached_chart(&rules, path)?,
+        }),
+        None => source.policy(&rules, options.seed),
+    };
+    let evaluation = evaluate(policy.as_mut(), &rules, options.hands, options.seed)?;
+    print!("{}", evaluation);
+    Ok(())
+}
+
+fn run_advise(options: AdviseOptions) -> Result<(), BlackjackError> {
+    let source = PolicySource::from(&options.policy)?;
+    let rules = source.rules(&options.rules);
+    let game_state = GameState::from_cards(rules.clone(), &options.cards, options.up_card)?;
+    let legal = game_state.legal_actions();
+    println!(
+        "Main : {} contre {}",
+        game_state.get_player_cards().value(),
+        options.up_card.rank
+    );
+
+    let mut advice = None;
+    match &source {
+        PolicySource::Table(table) => {
+            let state = table.encode(&game_state);
+            match table.states.get(&state) {
+                Some(values) => {
+                    for action in &legal {
+                        println!("  {:<10} {:+.4}", action, values[action.into_index()]);
+                    }
+                }
+                None => println!("  état jamais rencontré pendant l'entraînement"),
+            }
+        }
+        PolicySource::BuiltIn(name) if name == "basic" => {
+            // Values of the solver for these cards, the shoe is full except them and the up card
+            let cards: Vec<u8> = options.cards.iter().map(|card| card.value()).collect();
+            let values = Solver::with_rules(rules.clone()).values(&cards, options.up_card.value());
+            for action in &legal {
+                if let Some(value) = values.get(*action) {
+                    println!("  {:<10} {:+.4}", action, value);
+                }
+            }
+            // The values are solved already, no need to solve the whole chart (insurance is a side bet)
+            advice = legal
+                .iter()
+                .filter(|&&action| action != Action::Insurance)
+                .filter_map(|&action| values.get(action).map(|value| (action, value)))
+                .max_by(|(_, a), (_, b)| a.total_cmp(b))
+                .map(|(action, _)| action);
+        }
+        PolicySource::BuiltIn(_) => {}
+    }
+    let action = match advice {
+        Some(action) => action,
+        None => source.policy(&rules, 0).choose(&game_state),
+    };
+    println!("Meilleure action : {}", action);
+    Ok(())
+}
+
+fn run_export(options: ExportOptions) -> Result<(), BlackjackError> {
+    let chart = solve_chart(&options.rules);
+    let text = if options.json {
+        serde_json::to_string_pretty(&chart)?
+    } else {
+        chart.to_string()
+    };
+    match &options.output {
+        Some(path) => {
+            std::fs::write(path, text)?;
+            println!("Chart written to {}", path);
+        }
+        None => print!("{}", text),
+    }
+
+    if let Some(path) = &options.compare {
+        let table = QTable::load(path)?;
+        if table.metadata.rules != options.rules {
+            println!("Warning: {} was trained with other rules", path);
+        }
+        let (same, compared) = chart.agreement(&table);
+        println!(
+            "{} : {} of {} states play the basic strategy ({:.1}%)",
+            path,
+            same,
+            compared,
+            100.0 * same as f64 / compared.max(1) as f64
+        );
+    }
+    Ok(())
+}
+
+fn run_session(options: SessionOptions) -> Result<(), BlackjackError> {
+    let source = PolicySource::from(&options.policy)?;
+    let rules = source.rules(&options.rules);
+    let mut policy = source.policy(&rules, options.session.seed);
+    let report = simulate(policy.as_mut(), &rules, &options.session)?;
+    print!("{}", report);
+    Ok(())
+}
+
+fn run_risk(options: RiskOptions) -> Result<(), BlackjackError> {
+    let source = PolicySource::from(&options.policy)?;
+    let rules = source.rules(&options.rules);
+    let mut policy = source.policy(&rules, options.session.seed);
+    let report = analyse(
+        policy.as_mut(),
+        &rules,
+        &options.session,
+        options.hands,
+        options.kelly_fraction,
+        options.trajectories,
+    )?;
+    print!("{}", report);
+    Ok(())
+}
+
+fn run_table(options: TableOptions) -> Result<(), BlackjackError> {
+    // The tables are loaded first, the seats borrow them
+    let sources = options
+        .seats
+        .iter()
+        .map(|seat| match seat.as_str() {
+            "human" => Ok(None),
+            seat => PolicySource::from(seat).map(Some),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    for (seat, source) in options.seats.iter().zip(&sources) {
+        if let Some(source) = source
+            && source.rules(&options.rules) != options.rules
+        {
+            println!("Warning: {} was trained with other rules", seat);
+        }
+    }
+    let seed = options.seed.unwrap_or_else(rand::random);
+    // The chart is solved once for all the basic strategy seats
+    let mut chart: Option<StrategyChart> = None;
+    let mut policies: Vec<Box<dyn Policy + '_>> = Vec::new();
+    for (index, source) in sources.iter().enumerate() {
+        policies.push(match source {
+            None => Box::new(HumanPolicy),
+            Some(PolicySource::BuiltIn(name)) if name == "basic" => Box::new(ChartPolicy {
+                chart: chart
+                    .get_or_insert_with(|| solve_chart(&options.rules))
+                    .clone(),
+            }),
+            Some(source) => source.policy(&options.rules, thread_seed(seed, index)),
+        });
+    }
+    let mut table = Table::new(options.rules.clone(), policies, options.bet, Some(seed))?;
+
+    if !options.seats.iter().any(|seat| seat == "human") {
+        for _ in 0..options.rounds {
+            table.play_round()?;
+        }
+        print!("{}", table);
+        return Ok(());
+    }
+    let mut totals = vec![0.0; table.seats.len()];
+    loop {
+        table.deal()?;
+        println!(
+            "Carte visible du croupier : {}",
+            table.croupier_cards().get_card(0).unwrap()
+        );
+        for seat in 0..table.seats.len() {
+            println!(
+                "Place {} ({}) : {}",
+                seat + 1,
+                table.seats[seat].policy.name(),
+                table.seats[seat].state.get_player_cards().value()
+            );
+            table.play_seat(seat)?;
+            for hand in &table.seats[seat].state.hands {
+                println!("  main finale : {}", hand.cards.value());
+            }
+        }
+        let results = table.settle()?;
+        println!(
+            "Cartes du croupier : {}",
+            table
+                .croupier_cards()
+                .iterator()
+                .iter()
+                .map(|card| card.to_string())
+                .collect::<Vec<String>>()
+                .join(", ")
+        );
+        for (seat, result) in results.iter().enumerate() {
+            totals[seat] += result;
+            println!(
+                "Place {} : {:+}, total : {:+}",
+                seat + 1,
+                result,
+                totals[seat]
+            );
+        }
+
+        print!("Nouvelle partie ? (o/n) ");
+        io::stdout().flush()?;
+        let mut input = String::new();
+        if io::stdin().read_line(&mut input)? == 0
+            || !matches!(input.trim().to_lowercase().as_str(), "" | "o" | "oui" | "y")
+        {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn run_benchmark(max_threads: usize) {
+    let mut thread_counts = vec![1];
+    while thread_counts.last().unwrap() * 2 <= max_threads {
+        thread_counts.push(thread_counts.last().unwrap() * 2);
+    }
+    if *thread_counts.last().unwrap() != max_threads {
+        thread_counts.push(max_threads);
+    }
+
+    println!(
+        "{:>8} {:>10} {:>10} {:>12} {:>8}",
+        "threads", "episodes", "seconds", "episodes/s", "speedup"
+    );
+    let results = benchmark(BENCH_EPISODES, &thread_counts);
+    let base = results[0].episodes_per_second();
+    for result in &results {
+        println!(
+            "{:>8} {:>10} {:>10.2} {:>12.0} {:>7.2}x",
+            result.threads,
+            result.episodes,
+            result.seconds,
+            result.episodes_per_second(),
+            result.episodes_per_second() / base
+        );
     }
 }
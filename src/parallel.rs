@@ -0,0 +1,441 @@
+use crate::checkpoint::{CHECK_EVERY, Checkpointer, RunState, ThreadState};
+use crate::game::GameState;
+use crate::learning::*;
+use crate::rules::RuleSet;
+use crate::schedule::Behaviour;
+use crate::training::QTable;
+use std::sync::Arc;
+use std::sync::{Condvar, Mutex, PoisonError};
+use std::thread;
+use std::time::Instant;
+
+// (percentage, average reward, spinner frame) for each training thread
+pub type Progress = Arc<Mutex<Vec<(u8, f32, usize)>>>;
+
+// Every thread updates the shared DashMap directly, with its own generator derived from `seed`
+pub fn train_parallel(
+    table: &Arc<QTable>,
+    learner: &Arc<dyn Learner>,
+    nb_iterations: u64,
+    nb_threads: usize,
+    seed: u64,
+    progress: Option<&Progress>,
+) -> u64 {
+    let threads = thread_states(&table.metadata.rules, seed, nb_threads);
+    train_from(
+        table,
+        learner.as_ref(),
+        nb_iterations,
+        threads,
+        None,
+        progress,
+    )
+}
+
+// Starting point of the threads of a new run
+pub fn thread_states(rules: &RuleSet, seed: u64, nb_threads: usize) -> Vec<ThreadState> {
+    (0..nb_threads)
+        .map(|thread_id| ThreadState::new(rules, thread_seed(seed, thread_id)))
+        .collect()
+}
+
+// Seed of the generator of one thread, different for every thread of a run
+pub fn thread_seed(seed: u64, thread_id: usize) -> u64 {
+    seed ^ (thread_id as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+}
+
+// One thread for each state of `threads`. With a checkpointer they meet every CHECK_EVERY games
+// and one writes the checkpoint while the others wait, a thread that stops early stops them all.
+pub fn train_from(
+    table: &QTable,
+    learner: &dyn Learner,
+    nb_iterations: u64,
+    threads: Vec<ThreadState>,
+    checkpointer: Option<&Checkpointer>,
+    progress: Option<&Progress>,
+) -> u64 {
+    let meeting = checkpointer.map(|checkpointer| Meeting {
+        threads: threads.len(),
+        gate: Mutex::new(Gate::default()),
+        opened: Condvar::new(),
+        states: Mutex::new(vec![None; threads.len()]),
+        checkpointer,
+    });
+    let episodes = thread::scope(|scope| {
+        let handles: Vec<_> = threads
+            .into_iter()
+            .enumerate()
+            .map(|(thread_id, start)| {
+                let meeting = meeting.as_ref();
+                scope.spawn(move || {
+                    training_for_thread(
+                        nb_iterations,
+                        table,
+                        learner,
+                        progress,
+                        thread_id,
+                        start,
+                        meeting,
+                    )
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .sum()
+    });
+    learner.finish(table);
+    episodes
+}
+
+// Where the threads wait for each other before a checkpoint
+pub struct Meeting<'a> {
+    threads: usize,
+    gate: Mutex<Gate>,
+    opened: Condvar,
+    states: Mutex<Vec<Option<ThreadState>>>,
+    checkpointer: &'a Checkpointer,
+}
+
+// Barrier that a thread can break when it stops, unlike std::sync::Barrier
+#[derive(Default)]
+struct Gate {
+    arrived: usize,
+    generation: u64, // number of times every thread has met
+    broken: bool,
+}
+
+impl Meeting<'_> {
+    // Wait for every thread, Some(true) for the last one to arrive, None once the meeting is broken
+    fn wait(&self) -> Option<bool> {
+        let mut gate = self.gate.lock().unwrap();
+        if gate.broken {
+            return None;
+        }
+        gate.arrived += 1;
+        if gate.arrived == self.threads {
+            gate.arrived = 0;
+            gate.generation += 1;
+            self.opened.notify_all();
+            return Some(true);
+        }
+        let generation = gate.generation;
+        while gate.generation == generation && !gate.broken {
+            gate = self.opened.wait(gate).unwrap();
+        }
+        (gate.generation != generation).then_some(false)
+    }
+
+    // The thread will not come anymore, the threads waiting for it are let go
+    fn leave(&self) {
+        // called while a panic unwinds, a poisoned lock must not panic again
+        let mut gate = self.gate.lock().unwrap_or_else(PoisonError::into_inner);
+        gate.broken = true;
+        self.opened.notify_all();
+    }
+
+    // False when a thread has stopped, the training of this one is over too
+    fn checkpoint(
+        &self,
+        thread_id: usize,
+        state: ThreadState,
+        table: &QTable,
+        learner: &dyn Learner,
+        nb_iterations: u64,
+    ) -> bool {
+        self.states.lock().unwrap()[thread_id] = Some(state);
+        let Some(leader) = self.wait() else {
+            return false;
+        };
+        if leader && self.checkpointer.due(table.games()) {
+            let threads = self
+                .states
+                .lock()
+                .unwrap()
+                .iter_mut()
+                .map(|state| state.take().unwrap())
+                .collect();
+            let run = RunState {
+                episodes: nb_iterations,
+                checkpoint: 0,
+                threads,
+                learner: learner.saved_values(),
+                learner_visits: learner.saved_visits(),
+            };
+            // The training goes on without this checkpoint
+            if let Err(e) = self.checkpointer.save(table, run) {
+                eprintln!("Checkpoint not saved, {}", e);
+            }
+        }
+        self.wait().is_some()
+    }
+}
+
+// Breaks the meeting when its thread stops, whether it went to the end or panicked
+struct Leaving<'a>(Option<&'a Meeting<'a>>);
+
+impl Drop for Leaving<'_> {
+    fn drop(&mut self) {
+        if let Some(meeting) = self.0 {
+            meeting.leave();
+        }
+    }
+}
+
+// Play the games of one thread from `start` up to `nb_iterations`, returns the number of games that went to the end
+pub fn training_for_thread(
+    nb_iterations: u64,
+    table: &QTable,
+    learner: &dyn Learner,
+    progress: Option<&Progress>,
+    thread_id: usize,
+    start: ThreadState,
+    meeting: Option<&Meeting>,
+) -> u64 {
+    let mut avg_reward = 0.0;
+    let mut episodes = 0;
+    let ThreadState {
+        episode: first,
+        mut rng,
+        shoe,
+        discard,
+    } = start;
+    // The shoe is kept from one game to the next and reshuffled at the cut card
+    let mut env = table.environment(GameState::with_shoe(table.metadata.rules.clone(), shoe));
+    env.game_state.discard = discard;
+    let _leaving = Leaving(meeting);
+    for per in first..nb_iterations {
+        if let Some(meeting) = meeting
+            && per > first
+            && per % CHECK_EVERY == 0
+        {
+            let state = ThreadState {
+                episode: per,
+                rng: rng.clone(),
+                shoe: env.game_state.shoe.clone(),
+                discard: env.game_state.discard.clone(),
+            };
+            if !meeting.checkpoint(thread_id, state, table, learner, nb_iterations) {
+                break;
+            }
+        }
+        // The exploration of the table follows the progress of the thread
+        let behaviour = Behaviour {
+            table,
+            episode: per,
+            total: nb_iterations,
+        };
+        let reward = match train_episode(learner, table, &mut env, &behaviour, &mut rng) {
+            Ok(reward) => reward,
+            Err(e) => {
+                // The game is lost but the training goes on with a new shoe
+                eprintln!("Thread {}: game {} skipped, {}", thread_id, per, e);
+                env.game_state.shoe.shuffle();
+                continue;
+            }
+        };
+        episodes += 1;
+
+        // Mise à jour du pourcentage toutes les 1000 itérations
+        if per % 1000 == 0 || per == nb_iterations - 1 {
+            if let Some(progress) = progress {
+                let percent = ((per + 1) * 100 / nb_iterations) as u8;
+                let mut p = progress.lock().unwrap();
+                p[thread_id].0 = percent;
+                p[thread_id].1 = avg_reward;
+            }
+            avg_reward = 0.0;
+        } else {
+            avg_reward += reward;
+        }
+    }
+    episodes
+}
+
+pub struct BenchmarkResult {
+    pub threads: usize,
+    pub episodes: u64,
+    pub seconds: f64,
+}
+
+impl BenchmarkResult {
+    pub fn episodes_per_second(&self) -> f64 {
+        self.episodes as f64 / self.seconds
+    }
+}
+
+// Train a fresh table with each number of threads and measure the number of games per second
+pub fn benchmark(total_episodes: u64, thread_counts: &[usize]) -> Vec<BenchmarkResult> {
+    let mut results = Vec::new();
+    let learner: Arc<dyn Learner> = Arc::new(QLearning);
+    for &threads in thread_counts {
+        let table = Arc::new(QTable::new());
+        let per_thread = total_episodes / threads as u64;
+        let start = Instant::now();
+        let episodes = train_parallel(&table, &learner, per_thread, threads, 0, None);
+        results.push(BenchmarkResult {
+            threads,
+            episodes,
+            seconds: start.elapsed().as_secs_f64(),
+        });
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checkpoint::CheckpointOptions;
+    use crate::error::BlackjackError;
+    use crate::game::Action;
+    use crate::schedule::Behaviour;
+    use crate::training::{State, Transition};
+    use rand::RngCore;
+    use std::panic::AssertUnwindSafe;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn threads_train_one_shared_table() {
+        let table = Arc::new(QTable::new());
+        let progress: Progress = Arc::new(Mutex::new(vec![(0, 0.0, 0); 4]));
+        let learner: Arc<dyn Learner> = Arc::new(QLearning);
+        let episodes = train_parallel(&table, &learner, 500, 4, 7, Some(&progress));
+        assert_eq!(episodes, 4 * 500);
+        assert!(!table.is_empty());
+        assert!(
+            table
+                .states
+                .iter()
+                .all(|entry| entry.value().len() == Action::ALL.len())
+        );
+        assert!(progress.lock().unwrap().iter().all(|p| p.0 == 100));
+    }
+
+    fn sorted(table: &QTable) -> Vec<(State, Vec<f32>)> {
+        let mut states: Vec<_> = table
+            .states
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect();
+        states.sort_by(|a, b| a.0.cmp(&b.0));
+        states
+    }
+
+    fn train(seed: u64) -> Vec<(State, Vec<f32>)> {
+        let table = Arc::new(QTable::new());
+        let learner: Arc<dyn Learner> = Arc::new(DoubleQLearning::new());
+        train_parallel(&table, &learner, 2_000, 1, seed, None);
+        sorted(&table)
+    }
+
+    #[test]
+    fn same_seed_same_table() {
+        let first = train(11);
+        assert_eq!(first, train(11));
+        assert_ne!(first, train(12));
+        assert!(
+            first
+                .iter()
+                .all(|(_, values)| values.len() == Action::ALL.len())
+        );
+    }
+
+    #[test]
+    fn resumed_run_gives_the_same_table() {
+        let rules = RuleSet::default();
+        let path = std::env::temp_dir().join(format!(
+            "black_jack_ia_{}_resume_test.json",
+            std::process::id()
+        ));
+        let options = CheckpointOptions {
+            every: Some(1_000),
+            minutes: None,
+            keep: 1,
+            path: path.display().to_string(),
+        };
+        let whole = QTable::new();
+        let checkpointer = Checkpointer::new(options.clone(), 0, 0);
+        train_from(
+            &whole,
+            &DoubleQLearning::new(),
+            2_000,
+            thread_states(&rules, 5, 1),
+            Some(&checkpointer),
+            None,
+        );
+
+        // Start again from the checkpoint written halfway
+        let (resumed, run) = QTable::load_checkpoint(&options.file(1)).unwrap();
+        let run = run.unwrap();
+        assert_eq!(run.threads[0].episode, 1_000);
+        assert_eq!(resumed.games(), 1_000);
+        let learner = DoubleQLearning::new();
+        learner.restore_values(run.learner, run.learner_visits);
+        train_from(&resumed, &learner, run.episodes, run.threads, None, None);
+        std::fs::remove_file(options.file(1)).unwrap();
+
+        assert_eq!(resumed.games(), 2_000);
+        assert_eq!(sorted(&resumed), sorted(&whole));
+    }
+
+    // Q-learning whose thread panics on the game `fails_at` of the run
+    struct Failing {
+        games: AtomicU64,
+        fails_at: u64,
+    }
+
+    impl Learner for Failing {
+        fn name(&self) -> &'static str {
+            "failing"
+        }
+
+        fn learn(
+            &self,
+            table: &QTable,
+            episode: &[Transition],
+            behaviour: &Behaviour,
+            rng: &mut dyn RngCore,
+        ) -> Result<(), BlackjackError> {
+            if self.games.fetch_add(1, Ordering::Relaxed) == self.fails_at {
+                panic!("learner failed");
+            }
+            QLearning.learn(table, episode, behaviour, rng)
+        }
+    }
+
+    #[test]
+    fn a_failing_thread_does_not_leave_the_others_waiting() {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            // never due, the threads only meet
+            let options = CheckpointOptions {
+                every: None,
+                minutes: None,
+                keep: 1,
+                path: String::new(),
+            };
+            let checkpointer = Checkpointer::new(options, 0, 0);
+            let learner = Failing {
+                games: AtomicU64::new(0),
+                fails_at: CHECK_EVERY,
+            };
+            let threads = thread_states(&RuleSet::default(), 3, 2);
+            let run = std::panic::catch_unwind(AssertUnwindSafe(|| {
+                train_from(
+                    &QTable::new(),
+                    &learner,
+                    3 * CHECK_EVERY,
+                    threads,
+                    Some(&checkpointer),
+                    None,
+                )
+            }));
+            sender.send(run.is_err()).unwrap();
+        });
+        // the panic comes back instead of the other thread waiting forever
+        assert!(receiver.recv_timeout(Duration::from_secs(60)).unwrap());
+    }
+}
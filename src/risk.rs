@@ -0,0 +1,330 @@
+use crate::error::BlackjackError;
+use crate::evaluation::{Policy, RunningStats, play_round};
+use crate::game::GameState;
+use crate::parallel::thread_seed;
+use crate::rules::RuleSet;
+use crate::session::{Session, simulate};
+use std::fmt::Display;
+
+pub const RISK_MAX_TRUE_COUNT: i32 = 8; // True counts further than this from 0 are put together
+
+// Hands played at one true count for a bet of 1
+#[derive(Clone, Debug, Default)]
+pub struct CountStats {
+    pub true_count: i32,
+    pub results: RunningStats,
+}
+
+impl CountStats {
+    // Part of the bankroll to bet at this count (Kelly), nothing without an advantage
+    pub fn kelly(&self) -> f64 {
+        let variance = self.results.variance();
+        if self.results.mean > 0.0 && variance > 0.0 {
+            self.results.mean / variance
+        } else {
+            0.0
+        }
+    }
+}
+
+// Results of `n_hands` flat bets of 1, each one kept with the true count rounded down before it
+pub fn count_stats(
+    policy: &mut dyn Policy,
+    rules: &RuleSet,
+    session: &Session,
+    n_hands: u64,
+) -> Result<Vec<CountStats>, BlackjackError> {
+    let mut game_state = GameState::seeded(rules.clone(), session.seed);
+    let mut stats: Vec<CountStats> = (-RISK_MAX_TRUE_COUNT..=RISK_MAX_TRUE_COUNT)
+        .map(|true_count| CountStats {
+            true_count,
+            ..CountStats::default()
+        })
+        .collect();
+
+    for _ in 0..n_hands {
+        game_state.new_round();
+        let true_count = (session.system.true_count(&game_state.shoe).floor() as i32)
+            .clamp(-RISK_MAX_TRUE_COUNT, RISK_MAX_TRUE_COUNT);
+        play_round(&mut game_state, policy, |_| {})?;
+        stats[(true_count + RISK_MAX_TRUE_COUNT) as usize]
+            .results
+            .add(game_state.results(1.0) as f64);
+    }
+    stats.retain(|stats| stats.results.count > 0);
+    Ok(stats)
+}
+
+// Mean and variance of the money won per hand when betting with the ramp of `session`
+pub fn ramp_moments(stats: &[CountStats], session: &Session) -> (f64, f64) {
+    let hands: u64 = stats.iter().map(|stats| stats.results.count).sum();
+    let mut mean = 0.0;
+    let mut square = 0.0;
+    for stats in stats {
+        let results = &stats.results;
+        let frequency = results.count as f64 / hands.max(1) as f64;
+        let bet = session.bet(stats.true_count as f32) as f64;
+        mean += frequency * bet * results.mean;
+        square += frequency * bet * bet * (results.variance() + results.mean * results.mean);
+    }
+    (mean, square - mean * mean)
+}
+
+// Probability of ever losing `bankroll` when each hand wins `mean` with `variance` (Brownian motion)
+pub fn risk_of_ruin(mean: f64, variance: f64, bankroll: f64) -> f64 {
+    if mean <= 0.0 {
+        return 1.0;
+    }
+    (-2.0 * mean * bankroll / variance).exp().min(1.0)
+}
+
+// Same within the first `hands` hands
+pub fn risk_of_ruin_within(mean: f64, variance: f64, bankroll: f64, hands: u64) -> f64 {
+    let spread = (variance * hands as f64).sqrt();
+    let drift = mean * hands as f64;
+    let risk = normal_cdf((-bankroll - drift) / spread)
+        + (-2.0 * mean * bankroll / variance).exp() * normal_cdf((-bankroll + drift) / spread);
+    if risk.is_finite() { risk.min(1.0) } else { 1.0 }
+}
+
+// Distribution function of the standard normal law (Abramowitz and Stegun 7.1.26, error below 1.5e-7)
+fn normal_cdf(x: f64) -> f64 {
+    let z = x.abs() / std::f64::consts::SQRT_2;
+    let t = 1.0 / (1.0 + 0.3275911 * z);
+    let polynomial = t
+        * (0.254829592
+            + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    let erfc = polynomial * (-z * z).exp();
+    if x >= 0.0 {
+        1.0 - erfc / 2.0
+    } else {
+        erfc / 2.0
+    }
+}
+
+// Sessions played to the end or to the ruin, the ruin is a bankroll below the table minimum
+#[derive(Clone, Debug, Default)]
+pub struct MonteCarloRuin {
+    pub trajectories: u64,
+    pub ruined: u64,
+}
+
+impl MonteCarloRuin {
+    pub fn rate(&self) -> f64 {
+        self.ruined as f64 / self.trajectories.max(1) as f64
+    }
+
+    // 95% confidence interval of the rate
+    pub fn confidence_interval(&self) -> (f64, f64) {
+        let rate = self.rate();
+        let margin = 1.96 * (rate * (1.0 - rate) / self.trajectories.max(1) as f64).sqrt();
+        ((rate - margin).max(0.0), (rate + margin).min(1.0))
+    }
+}
+
+// Play `trajectories` sessions, each one from its own shoe
+pub fn monte_carlo_ruin(
+    policy: &mut dyn Policy,
+    rules: &RuleSet,
+    session: &Session,
+    trajectories: u64,
+) -> Result<MonteCarloRuin, BlackjackError> {
+    let mut result = MonteCarloRuin::default();
+    for trajectory in 0..trajectories {
+        let session = Session {
+            seed: thread_seed(session.seed, trajectory as usize),
+            ..session.clone()
+        };
+        result.trajectories += 1;
+        if simulate(policy, rules, &session)?.ruined {
+            result.ruined += 1;
+        }
+    }
+    Ok(result)
+}
+
+// Kelly bets per true count and the risk of ruin of the ramp, computed and simulated
+pub struct RiskReport {
+    pub policy: String,
+    pub system: String,
+    pub stats: Vec<CountStats>,
+    pub bankroll: f64,
+    pub kelly_fraction: f64, // part of the Kelly bet played, 1 for full Kelly
+    pub mean: f64,           // per hand with the ramp
+    pub variance: f64,
+    pub horizon: u64, // hands of each simulated session
+    pub monte_carlo: MonteCarloRuin,
+}
+
+impl Display for RiskReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let hands: u64 = self.stats.iter().map(|stats| stats.results.count).sum();
+        writeln!(
+            f,
+            "Policy: {}, count: {} ({} hands), bankroll {}",
+            self.policy, self.system, hands, self.bankroll
+        )?;
+        writeln!(
+            f,
+            "{:>4} {:>7} {:>8} {:>8} {:>10} {:>10} {:>10}",
+            "TC",
+            "freq %",
+            "EV %",
+            "SD",
+            "Kelly %",
+            "Kelly bet",
+            format!("x{} bet", self.kelly_fraction)
+        )?;
+        for stats in &self.stats {
+            let kelly = stats.kelly();
+            writeln!(
+                f,
+                "{:>4} {:>7.2} {:>+8.3} {:>8.3} {:>10.3} {:>10.2} {:>10.2}",
+                stats.true_count,
+                100.0 * stats.results.count as f64 / hands.max(1) as f64,
+                100.0 * stats.results.mean,
+                stats.results.std_dev(),
+                100.0 * kelly,
+                kelly * self.bankroll,
+                kelly * self.kelly_fraction * self.bankroll
+            )?;
+        }
+        writeln!(
+            f,
+            "Bet ramp: mean {:+.4} and standard deviation {:.4} per hand",
+            self.mean,
+            self.variance.sqrt()
+        )?;
+        writeln!(
+            f,
+            "Risk of ruin, analytic: {:.2}% forever, {:.2}% within {} hands",
+            100.0 * risk_of_ruin(self.mean, self.variance, self.bankroll),
+            100.0 * risk_of_ruin_within(self.mean, self.variance, self.bankroll, self.horizon),
+            self.horizon
+        )?;
+        let (low, high) = self.monte_carlo.confidence_interval();
+        writeln!(
+            f,
+            "Risk of ruin, simulated: {:.2}% (95% CI {:.2}% .. {:.2}%) within {} hands over {} sessions",
+            100.0 * self.monte_carlo.rate(),
+            100.0 * low,
+            100.0 * high,
+            self.horizon,
+            self.monte_carlo.trajectories
+        )
+    }
+}
+
+// The ramp of `session` valued from flat bets per true count, its risk of ruin checked by `trajectories` sessions
+pub fn analyse(
+    policy: &mut dyn Policy,
+    rules: &RuleSet,
+    session: &Session,
+    n_hands: u64,
+    kelly_fraction: f64,
+    trajectories: u64,
+) -> Result<RiskReport, BlackjackError> {
+    let stats = count_stats(policy, rules, session, n_hands)?;
+    let (mean, variance) = ramp_moments(&stats, session);
+    let monte_carlo = monte_carlo_ruin(policy, rules, session, trajectories)?;
+    Ok(RiskReport {
+        policy: policy.name(),
+        system: session.system.name().to_string(),
+        stats,
+        bankroll: session.bankroll as f64,
+        kelly_fraction,
+        mean,
+        variance,
+        horizon: session.hands,
+        monte_carlo,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::counting::HI_LO;
+    use crate::evaluation::RandomPolicy;
+    use crate::session::BetRamp;
+
+    fn session(bankroll: f32, hands: u64) -> Session {
+        Session {
+            hands,
+            bankroll,
+            unit: 10.0,
+            ramp: BetRamp::default(),
+            table_min: 10.0,
+            table_max: 100.0,
+            system: &HI_LO,
+            seed: 6,
+        }
+    }
+
+    #[test]
+    fn ruin_formulas_agree() {
+        // a player losing on average is ruined some day
+        assert_eq!(risk_of_ruin(-0.01, 1.3, 100.0), 1.0);
+        let forever = risk_of_ruin(0.01, 1.3, 100.0);
+        assert!((forever - (-2.0 * 0.01 * 100.0 / 1.3f64).exp()).abs() < 1e-12);
+        // the risk grows with the number of hands up to the risk of playing forever
+        let short = risk_of_ruin_within(0.01, 1.3, 100.0, 1_000);
+        let long = risk_of_ruin_within(0.01, 1.3, 100.0, 10_000_000);
+        assert!(short < long);
+        assert!((long - forever).abs() < 1e-4);
+        assert!((normal_cdf(1.96) - 0.975).abs() < 1e-4);
+    }
+
+    #[test]
+    fn count_stats_split_the_flat_bets_by_true_count() {
+        let rules = RuleSet::default();
+        let session = session(1_000.0, 100);
+        let stats = count_stats(&mut RandomPolicy::new(2), &rules, &session, 5_000).unwrap();
+        assert_eq!(
+            stats.iter().map(|stats| stats.results.count).sum::<u64>(),
+            5_000
+        );
+        assert!(stats.windows(2).all(|w| w[0].true_count < w[1].true_count));
+        assert!(
+            stats
+                .iter()
+                .all(|stats| stats.true_count.abs() <= RISK_MAX_TRUE_COUNT)
+        );
+
+        // the same hands played one after the other win the same money
+        let mut policy = RandomPolicy::new(2);
+        let mut game_state = GameState::seeded(rules.clone(), session.seed);
+        let mut won = 0.0;
+        for _ in 0..5_000 {
+            game_state.new_round();
+            play_round(&mut game_state, &mut policy, |_| {}).unwrap();
+            won += game_state.results(1.0) as f64;
+        }
+        let total: f64 = stats
+            .iter()
+            .map(|stats| stats.results.mean * stats.results.count as f64)
+            .sum();
+        assert!((total - won).abs() < 1e-6);
+    }
+
+    #[test]
+    fn monte_carlo_ruin_counts_the_ruined_sessions() {
+        let rules = RuleSet::default();
+        // two table minimums never last 2000 random hands, a big bankroll outlives 20 hands
+        let ruined =
+            monte_carlo_ruin(&mut RandomPolicy::new(1), &rules, &session(20.0, 2_000), 20).unwrap();
+        assert_eq!((ruined.trajectories, ruined.ruined), (20, 20));
+        assert_eq!(ruined.confidence_interval(), (1.0, 1.0));
+        let safe =
+            monte_carlo_ruin(&mut RandomPolicy::new(1), &rules, &session(1e6, 20), 20).unwrap();
+        assert_eq!((safe.trajectories, safe.ruined), (20, 0));
+
+        // the same seed gives the same sessions
+        let first =
+            monte_carlo_ruin(&mut RandomPolicy::new(3), &rules, &session(300.0, 500), 30).unwrap();
+        let again =
+            monte_carlo_ruin(&mut RandomPolicy::new(3), &rules, &session(300.0, 500), 30).unwrap();
+        assert_eq!(again.ruined, first.ruined);
+        let (low, high) = first.confidence_interval();
+        assert!(low <= first.rate() && first.rate() <= high);
+    }
+}
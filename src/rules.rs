@@ -0,0 +1,181 @@
+use crate::card::HandValue;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlackjackPayout {
+    ThreeToTwo,
+    SixToFive,
+    EvenMoney,
+}
+
+impl BlackjackPayout {
+    // Amount won for each unit bet
+    pub fn ratio(&self) -> f32 {
+        match self {
+            BlackjackPayout::ThreeToTwo => 1.5,
+            BlackjackPayout::SixToFive => 1.2,
+            BlackjackPayout::EvenMoney => 1.0,
+        }
+    }
+}
+
+// Totals on which the player is allowed to double down
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DoubleRule {
+    AnyTwo,
+    NineToEleven,
+    TenToEleven,
+}
+
+impl DoubleRule {
+    pub fn allows(&self, hand: &HandValue) -> bool {
+        match self {
+            DoubleRule::AnyTwo => true,
+            DoubleRule::NineToEleven => (9..=11).contains(&hand.total()),
+            DoubleRule::TenToEleven => (10..=11).contains(&hand.total()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Surrender {
+    None,
+    Late,  // after the dealer has checked for blackjack
+    Early, // before the dealer checks for blackjack
+}
+
+// Rules that change from one casino to another, the default is a common 6 decks game
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RuleSet {
+    pub decks: u8,
+    pub dealer_hits_soft_17: bool,
+    pub blackjack_payout: BlackjackPayout,
+    pub double: DoubleRule,
+    pub double_after_split: bool,
+    pub max_splits: u8, // number of splits allowed in one round, 3 means up to 4 hands
+    pub resplit_aces: bool, // split again when an ace is dealt on split aces
+    pub hit_split_aces: bool, // when false, split aces only receive one card each
+    pub surrender: Surrender,
+    pub insurance: bool,
+    pub dealer_peek: bool, // dealer checks his hole card for blackjack under an ace or a ten
+    pub no_hole_card: bool, // European game (ENHC) : the dealer's second card is dealt after the players
+}
+
+impl Default for RuleSet {
+    fn default() -> Self {
+        RuleSet {
+            decks: 6,
+            dealer_hits_soft_17: false,
+            blackjack_payout: BlackjackPayout::ThreeToTwo,
+            double: DoubleRule::AnyTwo,
+            double_after_split: true,
+            max_splits: 3,
+            resplit_aces: false,
+            hit_split_aces: false,
+            surrender: Surrender::None,
+            insurance: true,
+            dealer_peek: true,
+            no_hole_card: false,
+        }
+    }
+}
+
+impl RuleSet {
+    // Dealer draws under 17, and on soft 17 when the table says so
+    pub fn croupier_must_draw(&self, hand: &HandValue) -> bool {
+        hand.total() < 17 || (self.dealer_hits_soft_17 && hand.total() == 17 && hand.is_soft())
+    }
+
+    pub fn can_double(&self, hand: &HandValue) -> bool {
+        self.double.allows(hand)
+    }
+}
+
+impl Display for RuleSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let payout = match self.blackjack_payout {
+            BlackjackPayout::ThreeToTwo => "3:2",
+            BlackjackPayout::SixToFive => "6:5",
+            BlackjackPayout::EvenMoney => "1:1",
+        };
+        let double = match self.double {
+            DoubleRule::AnyTwo => "any two cards",
+            DoubleRule::NineToEleven => "9-11",
+            DoubleRule::TenToEleven => "10-11",
+        };
+        write!(
+            f,
+            "{} decks, {}, blackjack pays {}, double on {}{}",
+            self.decks,
+            if self.dealer_hits_soft_17 {
+                "H17"
+            } else {
+                "S17"
+            },
+            payout,
+            double,
+            if self.double_after_split { ", DAS" } else { "" },
+        )?;
+        write!(f, ", split to {} hands", self.max_splits + 1)?;
+        if self.resplit_aces {
+            write!(f, ", RSA")?;
+        }
+        if self.hit_split_aces {
+            write!(f, ", hit split aces")?;
+        }
+        match self.surrender {
+            Surrender::None => {}
+            Surrender::Late => write!(f, ", late surrender")?,
+            Surrender::Early => write!(f, ", early surrender")?,
+        }
+        if !self.insurance {
+            write!(f, ", no insurance")?;
+        }
+        if self.no_hole_card {
+            write!(f, ", ENHC")?;
+        } else if !self.dealer_peek {
+            write!(f, ", no peek")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::card::{PackOfCards, Rank};
+
+    fn value(ranks: &[Rank]) -> HandValue {
+        PackOfCards::from_ranks(ranks).value()
+    }
+
+    #[test]
+    fn only_h17_dealers_draw_on_soft_17() {
+        let s17 = RuleSet::default();
+        let h17 = RuleSet {
+            dealer_hits_soft_17: true,
+            ..RuleSet::default()
+        };
+        let soft_17 = value(&[Rank::Ace, Rank::Six]);
+        assert!(!s17.croupier_must_draw(&soft_17));
+        assert!(h17.croupier_must_draw(&soft_17));
+        // a hard 17 always stands, a 16 always draws
+        let hard_17 = value(&[Rank::King, Rank::Seven]);
+        assert!(!h17.croupier_must_draw(&hard_17));
+        assert!(s17.croupier_must_draw(&value(&[Rank::King, Rank::Six])));
+    }
+
+    #[test]
+    fn double_rule_limits_the_totals() {
+        let rules = RuleSet {
+            double: DoubleRule::TenToEleven,
+            ..RuleSet::default()
+        };
+        assert!(rules.can_double(&value(&[Rank::Six, Rank::Four])));
+        assert!(!rules.can_double(&value(&[Rank::Five, Rank::Four])));
+        // a soft 20 is not a 10
+        assert!(!rules.can_double(&value(&[Rank::Ace, Rank::Nine])));
+        assert!(RuleSet::default().can_double(&value(&[Rank::Ace, Rank::Nine])));
+    }
+}
@@ -0,0 +1,310 @@
+use crate::error::BlackjackError;
+use crate::game::Action;
+use crate::training::{QTable, State, greedy_action, select_action};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::str::FromStr;
+
+// Numbers of a schedule written `name:x:y:z`
+fn numbers(s: &str, parts: &[&str]) -> Result<Vec<f32>, String> {
+    parts
+        .iter()
+        .map(|part| part.parse::<f32>())
+        .collect::<Result<Vec<f32>, _>>()
+        .map_err(|_| format!("invalid number in schedule '{}'", s))
+}
+
+fn check(s: &str, ok: bool) -> Result<(), String> {
+    if ok {
+        Ok(())
+    } else {
+        Err(format!("schedule '{}' : value out of range", s))
+    }
+}
+
+fn probability(x: f32) -> bool {
+    (0.0..=1.0).contains(&x)
+}
+
+// Probability of a random legal action at game `episode` out of `total`, `visits` updates in the state
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum EpsilonSchedule {
+    Constant(f32),
+    // from `start` to `end` over the first `decay` part of the games, then `end`
+    Linear { start: f32, end: f32, decay: f32 },
+    // start × decay^episode, never below `end`
+    Exponential { start: f32, end: f32, decay: f32 },
+    // start / (1 + k × episode)
+    InverseTime { start: f32, k: f32 },
+    // c / (c + visits of the state) : explore less where the table already knows
+    Visits { c: f32 },
+}
+
+impl Default for EpsilonSchedule {
+    fn default() -> Self {
+        EpsilonSchedule::Linear {
+            start: 1.0,
+            end: 0.02,
+            decay: 0.05,
+        }
+    }
+}
+
+impl EpsilonSchedule {
+    pub fn epsilon(&self, episode: u64, total: u64, visits: u64) -> f32 {
+        match *self {
+            EpsilonSchedule::Constant(epsilon) => epsilon,
+            EpsilonSchedule::Linear { start, end, decay } => {
+                let progress = episode as f32 / (total.max(1) as f32 * decay).max(1.0);
+                if progress >= 1.0 {
+                    end
+                } else {
+                    start + (end - start) * progress
+                }
+            }
+            EpsilonSchedule::Exponential { start, end, decay } => {
+                (start * decay.powf(episode as f32)).max(end)
+            }
+            EpsilonSchedule::InverseTime { start, k } => start / (1.0 + k * episode as f32),
+            EpsilonSchedule::Visits { c } => c / (c + visits as f32),
+        }
+    }
+}
+
+// Written `constant:E`, `linear:START:END:PART`, `exponential:START:END:DECAY`, `inverse-time:START:K` or `visits:C`
+impl FromStr for EpsilonSchedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(':').collect();
+        let numbers = numbers(s, &parts[1..])?;
+        let schedule = match (parts[0], numbers.as_slice()) {
+            ("constant", &[epsilon]) => EpsilonSchedule::Constant(epsilon),
+            ("linear", &[start, end, decay]) => EpsilonSchedule::Linear { start, end, decay },
+            ("exponential", &[start, end, decay]) => {
+                EpsilonSchedule::Exponential { start, end, decay }
+            }
+            ("inverse-time", &[start, k]) => EpsilonSchedule::InverseTime { start, k },
+            ("visits", &[c]) => EpsilonSchedule::Visits { c },
+            _ => {
+                return Err(format!(
+                    "unknown epsilon schedule '{}', expected constant:E, linear:START:END:PART, \
+                     exponential:START:END:DECAY, inverse-time:START:K or visits:C",
+                    s
+                ));
+            }
+        };
+        schedule.validate()?;
+        Ok(schedule)
+    }
+}
+
+impl EpsilonSchedule {
+    pub fn validate(&self) -> Result<(), String> {
+        let ok = match *self {
+            EpsilonSchedule::Constant(epsilon) => probability(epsilon),
+            EpsilonSchedule::Linear { start, end, decay }
+            | EpsilonSchedule::Exponential { start, end, decay } => {
+                probability(start) && probability(end) && probability(decay)
+            }
+            EpsilonSchedule::InverseTime { start, k } => {
+                probability(start) && k >= 0.0 && k.is_finite()
+            }
+            EpsilonSchedule::Visits { c } => c > 0.0 && c.is_finite(),
+        };
+        check(&self.to_string(), ok)
+    }
+}
+
+impl Display for EpsilonSchedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EpsilonSchedule::Constant(epsilon) => write!(f, "constant:{}", epsilon),
+            EpsilonSchedule::Linear { start, end, decay } => {
+                write!(f, "linear:{}:{}:{}", start, end, decay)
+            }
+            EpsilonSchedule::Exponential { start, end, decay } => {
+                write!(f, "exponential:{}:{}:{}", start, end, decay)
+            }
+            EpsilonSchedule::InverseTime { start, k } => write!(f, "inverse-time:{}:{}", start, k),
+            EpsilonSchedule::Visits { c } => write!(f, "visits:{}", c),
+        }
+    }
+}
+
+// Step of each update, `visits` counts the updates of the pair including this one
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum LearningRate {
+    Constant(f32),
+    // 1 / N(s, a) : the value is the mean of every target seen
+    Visits,
+    // start × decay^games, never below `min`
+    Exponential { start: f32, decay: f32, min: f32 },
+}
+
+impl Default for LearningRate {
+    fn default() -> Self {
+        LearningRate::Constant(0.1)
+    }
+}
+
+impl LearningRate {
+    pub fn alpha(&self, visits: u64, games: u64) -> f32 {
+        match *self {
+            LearningRate::Constant(alpha) => alpha,
+            LearningRate::Visits => 1.0 / visits.max(1) as f32,
+            LearningRate::Exponential { start, decay, min } => {
+                (start * decay.powf(games as f32)).max(min)
+            }
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let ok = match *self {
+            LearningRate::Constant(alpha) => alpha > 0.0 && alpha <= 1.0,
+            LearningRate::Visits => true,
+            LearningRate::Exponential { start, decay, min } => {
+                start > 0.0 && start <= 1.0 && probability(decay) && probability(min)
+            }
+        };
+        check(&self.to_string(), ok)
+    }
+}
+
+// Written `constant:A`, `visits` or `exponential:START:DECAY:MIN`
+impl FromStr for LearningRate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(':').collect();
+        let numbers = numbers(s, &parts[1..])?;
+        let rate = match (parts[0], numbers.as_slice()) {
+            ("constant", &[alpha]) => LearningRate::Constant(alpha),
+            ("visits", &[]) => LearningRate::Visits,
+            ("exponential", &[start, decay, min]) => {
+                LearningRate::Exponential { start, decay, min }
+            }
+            _ => {
+                return Err(format!(
+                    "unknown learning rate '{}', expected constant:A, visits or \
+                     exponential:START:DECAY:MIN",
+                    s
+                ));
+            }
+        };
+        rate.validate()?;
+        Ok(rate)
+    }
+}
+
+impl Display for LearningRate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LearningRate::Constant(alpha) => write!(f, "constant:{}", alpha),
+            LearningRate::Visits => write!(f, "visits"),
+            LearningRate::Exponential { start, decay, min } => {
+                write!(f, "exponential:{}:{}:{}", start, decay, min)
+            }
+        }
+    }
+}
+
+// How the actions are chosen during the training
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Exploration {
+    EpsilonGreedy(EpsilonSchedule),
+    // softmax of the values divided by a temperature going from `start` to `end` over the games
+    Boltzmann { start: f32, end: f32 },
+}
+
+impl Default for Exploration {
+    fn default() -> Self {
+        Exploration::EpsilonGreedy(EpsilonSchedule::default())
+    }
+}
+
+impl Exploration {
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Exploration::EpsilonGreedy(schedule) => schedule.validate(),
+            Exploration::Boltzmann { start, end } => check(
+                &format!("boltzmann:{}:{}", start, end),
+                *start > 0.0 && *end > 0.0 && start.is_finite() && end.is_finite(),
+            ),
+        }
+    }
+}
+
+// Exploration of the table at game `episode` out of `total`, also the target policy of Expected SARSA
+pub struct Behaviour<'a> {
+    pub table: &'a QTable,
+    pub episode: u64,
+    pub total: u64,
+}
+
+impl Behaviour<'_> {
+    fn epsilon(&self, schedule: &EpsilonSchedule, state: &State) -> f32 {
+        schedule.epsilon(self.episode, self.total, self.table.state_visits(state))
+    }
+
+    // Probability of each legal action of `state`, in the order of State::legal_actions
+    pub fn probabilities(&self, state: &State, values: &[f32]) -> Vec<f32> {
+        let actions = state.legal_actions();
+        match &self.table.metadata.exploration {
+            Exploration::EpsilonGreedy(schedule) => {
+                let epsilon = self.epsilon(schedule, state);
+                let best = greedy_action(state, values).ok();
+                actions
+                    .iter()
+                    .map(|action| {
+                        let greedy = if Some(*action) == best {
+                            1.0 - epsilon
+                        } else {
+                            0.0
+                        };
+                        greedy + epsilon / actions.len() as f32
+                    })
+                    .collect()
+            }
+            Exploration::Boltzmann { start, end } => {
+                let progress = self.episode as f32 / self.total.max(1) as f32;
+                let temperature = start * (end / start).powf(progress);
+                let max = actions
+                    .iter()
+                    .map(|action| values[action.into_index()])
+                    .fold(f32::NEG_INFINITY, f32::max);
+                let weights: Vec<f32> = actions
+                    .iter()
+                    .map(|action| ((values[action.into_index()] - max) / temperature).exp())
+                    .collect();
+                let sum: f32 = weights.iter().sum();
+                weights.iter().map(|weight| weight / sum).collect()
+            }
+        }
+    }
+
+    pub fn choose<R: Rng + ?Sized>(
+        &self,
+        state: &State,
+        values: &[f32],
+        rng: &mut R,
+    ) -> Result<Action, BlackjackError> {
+        if let Exploration::EpsilonGreedy(schedule) = &self.table.metadata.exploration {
+            return select_action(state, values, self.epsilon(schedule, state), rng);
+        }
+        let probabilities = self.probabilities(state, values);
+        let mut draw: f32 = rng.r#gen();
+        for (action, p) in state.legal_actions().iter().zip(&probabilities) {
+            if draw < *p {
+                return Ok(*action);
+            }
+            draw -= p;
+        }
+        state
+            .legal_actions()
+            .last()
+            .copied()
+            .ok_or_else(|| BlackjackError::NoLegalAction(state.clone()))
+    }
+}
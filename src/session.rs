@@ -0,0 +1,272 @@
+use crate::counting::CountingSystem;
+use crate::error::BlackjackError;
+use crate::evaluation::{Policy, RunningStats, play_round};
+use crate::game::GameState;
+use crate::risk::risk_of_ruin;
+use crate::rules::RuleSet;
+use std::fmt::Display;
+use std::str::FromStr;
+
+// Units bet for each true count rounded down, one unit below the first step
+#[derive(Clone, Debug, PartialEq)]
+pub struct BetRamp {
+    pub steps: Vec<(i32, f32)>, // (lowest true count, units), sorted by true count
+}
+
+impl Default for BetRamp {
+    // 1 to 12 spread for a six decks shoe
+    fn default() -> Self {
+        BetRamp {
+            steps: vec![(2, 2.0), (3, 4.0), (4, 8.0), (5, 12.0)],
+        }
+    }
+}
+
+impl BetRamp {
+    pub fn units(&self, true_count: f32) -> f32 {
+        let true_count = true_count.floor() as i32;
+        self.steps
+            .iter()
+            .rev()
+            .find(|(count, _)| true_count >= *count)
+            .map_or(1.0, |(_, units)| *units)
+    }
+
+    // Largest bet over the smallest one
+    pub fn spread(&self) -> f32 {
+        let units = self.steps.iter().map(|(_, units)| *units);
+        units.clone().fold(1.0, f32::max) / units.fold(1.0, f32::min)
+    }
+}
+
+// Written `TC:UNITS,TC:UNITS,...`, for example `2:2,3:4,4:8,5:12`
+impl FromStr for BetRamp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut steps = Vec::new();
+        for step in s.split(',') {
+            let parsed = step.split_once(':').and_then(|(count, units)| {
+                Some((count.trim().parse().ok()?, units.trim().parse().ok()?))
+            });
+            match parsed {
+                Some((count, units)) if units > 0.0 => steps.push((count, units)),
+                _ => {
+                    return Err(format!(
+                        "invalid step '{}' in bet ramp '{}', expected TC:UNITS",
+                        step, s
+                    ));
+                }
+            }
+        }
+        steps.sort_by_key(|(count, _)| *count);
+        if steps.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+            return Err(format!("true count given twice in bet ramp '{}'", s));
+        }
+        Ok(BetRamp { steps })
+    }
+}
+
+impl Display for BetRamp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let steps: Vec<String> = self
+            .steps
+            .iter()
+            .map(|(count, units)| format!("{}:{}", count, units))
+            .collect();
+        write!(f, "{}", steps.join(","))
+    }
+}
+
+// How the money is played during a session
+#[derive(Clone)]
+pub struct Session {
+    pub hands: u64,
+    pub bankroll: f32,
+    pub unit: f32, // bet of one unit of the ramp
+    pub ramp: BetRamp,
+    pub table_min: f32,
+    pub table_max: f32,
+    pub system: &'static dyn CountingSystem,
+    pub seed: u64, // seed of the shoe
+}
+
+impl Session {
+    // Bet of the next hand, within the limits of the table
+    pub fn bet(&self, true_count: f32) -> f32 {
+        (self.unit * self.ramp.units(true_count)).clamp(self.table_min, self.table_max)
+    }
+}
+
+// Money won over the hands of a session, the statistics are per hand
+#[derive(Clone, Debug, Default)]
+pub struct SessionReport {
+    pub policy: String,
+    pub system: String,
+    pub hands: u64, // fewer than asked when the bankroll ran out
+    pub bankroll: f32,
+    pub final_bankroll: f32,
+    pub ruined: bool,
+    pub total_bet: f64, // sum of the first bets of the hands
+    pub mean: f64,
+    pub std_dev: f64,
+}
+
+impl SessionReport {
+    pub fn win_rate_per_100(&self) -> f64 {
+        100.0 * self.mean
+    }
+
+    pub fn std_dev_per_100(&self) -> f64 {
+        10.0 * self.std_dev
+    }
+
+    // Money won for each unit of initial bet
+    pub fn advantage(&self) -> f64 {
+        self.mean * self.hands as f64 / self.total_bet.max(f64::MIN_POSITIVE)
+    }
+
+    // Hands needed before the expected win is one standard deviation
+    pub fn n0(&self) -> f64 {
+        if self.mean > 0.0 {
+            (self.std_dev / self.mean).powi(2)
+        } else {
+            f64::INFINITY
+        }
+    }
+
+    // Win per 100 hands with a bankroll of 10 000 bet at the Kelly optimum, the same as 10^6 / N0
+    pub fn score(&self) -> f64 {
+        if self.mean > 0.0 {
+            1e6 * (self.mean / self.std_dev).powi(2)
+        } else {
+            0.0
+        }
+    }
+
+    // Probability of losing `bankroll` some day playing forever like this session
+    pub fn risk_of_ruin(&self, bankroll: f64) -> f64 {
+        risk_of_ruin(self.mean, self.std_dev.powi(2), bankroll)
+    }
+}
+
+impl Display for SessionReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Policy: {}, count: {} ({} hands)",
+            self.policy, self.system, self.hands
+        )?;
+        writeln!(
+            f,
+            "Bankroll: {} -> {}{}",
+            self.bankroll,
+            self.final_bankroll,
+            if self.ruined { " (ruined)" } else { "" }
+        )?;
+        writeln!(
+            f,
+            "Win rate per 100 hands: {:+.2}, standard deviation per 100 hands: {:.2}",
+            self.win_rate_per_100(),
+            self.std_dev_per_100()
+        )?;
+        writeln!(
+            f,
+            "Advantage: {:+.3}%  N0: {:.0} hands  SCORE: {:.2}",
+            100.0 * self.advantage(),
+            self.n0(),
+            self.score()
+        )?;
+        writeln!(
+            f,
+            "Risk of ruin of a bankroll of {}: {:.2}%",
+            self.bankroll,
+            100.0 * self.risk_of_ruin(self.bankroll as f64)
+        )
+    }
+}
+
+// Up to `session.hands` hands bet on the true count, until the bankroll cannot pay the table minimum
+pub fn simulate(
+    policy: &mut dyn Policy,
+    rules: &RuleSet,
+    session: &Session,
+) -> Result<SessionReport, BlackjackError> {
+    let mut game_state = GameState::seeded(rules.clone(), session.seed);
+    let mut report = SessionReport {
+        policy: policy.name(),
+        system: session.system.name().to_string(),
+        bankroll: session.bankroll,
+        final_bankroll: session.bankroll,
+        ..SessionReport::default()
+    };
+    let mut money = session.bankroll;
+    let mut results = RunningStats::default();
+
+    for _ in 0..session.hands {
+        game_state.new_round();
+        let bet = session
+            .bet(session.system.true_count(&game_state.shoe))
+            .min(money);
+        if bet < session.table_min {
+            report.ruined = true;
+            break;
+        }
+        game_state.set_bankroll(money, bet);
+        play_round(&mut game_state, policy, |_| {})?;
+        let result = game_state.results(bet);
+        money += result;
+        report.total_bet += bet as f64;
+        results.add(result as f64);
+    }
+    report.hands = results.count;
+    report.mean = results.mean;
+    report.std_dev = results.std_dev();
+    report.final_bankroll = money;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::counting::HI_LO;
+    use crate::evaluation::RandomPolicy;
+
+    fn session(bankroll: f32) -> Session {
+        Session {
+            hands: 2_000,
+            bankroll,
+            unit: 10.0,
+            ramp: BetRamp::default(),
+            table_min: 10.0,
+            table_max: 100.0,
+            system: &HI_LO,
+            seed: 4,
+        }
+    }
+
+    #[test]
+    fn ramp_follows_the_true_count_within_the_limits() {
+        let ramp: BetRamp = "3:4,2:2,5:12,4:8".parse().unwrap();
+        assert_eq!(ramp, BetRamp::default());
+        assert_eq!(ramp.units(1.9), 1.0);
+        assert_eq!(ramp.units(3.5), 4.0);
+        assert_eq!(ramp.units(-4.0), 1.0);
+        assert_eq!(ramp.spread(), 12.0);
+        assert_eq!(session(1_000.0).bet(9.0), 100.0);
+        assert!("2:2,2:4".parse::<BetRamp>().is_err());
+    }
+
+    #[test]
+    fn small_bankroll_is_ruined() {
+        let report = simulate(
+            &mut RandomPolicy::new(1),
+            &RuleSet::default(),
+            &session(50.0),
+        )
+        .unwrap();
+        assert!(report.ruined);
+        assert!(report.hands < 2_000);
+        assert!(report.final_bankroll < 10.0);
+    }
+}
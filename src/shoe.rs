@@ -0,0 +1,305 @@
+use crate::card::*;
+use crate::error::BlackjackError;
+use rand::SeedableRng;
+use rand_chacha::ChaCha12Rng;
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_DECKS: u8 = 6;
+pub const DEFAULT_PENETRATION: f32 = 0.75;
+pub const DEFAULT_BURN_CARDS: u8 = 1;
+
+// Several decks kept between the hands, reshuffled once the cut card at `penetration` comes out
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Shoe {
+    cards: PackOfCards, // cards that have not been dealt yet
+    decks: u8,
+    penetration: f32, // part of the shoe dealt before the cut card
+    burn_cards: u8,   // cards discarded face down after each shuffle
+    cut_card: usize,  // number of cards left in the shoe when the cut card comes out
+    seen: [u16; 10],  // cards shown since the shuffle by value, ace first, see counting.rs
+    rng: ChaCha12Rng, // generator of the shuffles, the one of StdRng but its state can be saved
+    #[serde(default)]
+    in_play: Vec<Card>, // cards dealt since the start of the round, see end_round
+    #[serde(default)]
+    face_down: Vec<Card>, // cards of the round not turned over yet
+}
+
+impl Default for Shoe {
+    fn default() -> Self {
+        Shoe::new(DEFAULT_DECKS, DEFAULT_PENETRATION, DEFAULT_BURN_CARDS)
+    }
+}
+
+impl Shoe {
+    pub fn new(decks: u8, penetration: f32, burn_cards: u8) -> Shoe {
+        Shoe::with_rng(decks, penetration, burn_cards, ChaCha12Rng::from_entropy())
+    }
+
+    // Shoe whose shuffles are always the same for the same seed
+    pub fn seeded(decks: u8, penetration: f32, burn_cards: u8, seed: u64) -> Shoe {
+        Shoe::with_rng(
+            decks,
+            penetration,
+            burn_cards,
+            ChaCha12Rng::seed_from_u64(seed),
+        )
+    }
+
+    // Shoe without any card that is never filled again, for a seat dealt from the shoe of its table
+    pub fn empty() -> Shoe {
+        Shoe {
+            cards: PackOfCards::new(),
+            decks: 0,
+            penetration: 0.0,
+            burn_cards: 0,
+            cut_card: 0,
+            seen: [0; 10],
+            rng: ChaCha12Rng::seed_from_u64(0),
+            in_play: Vec::new(),
+            face_down: Vec::new(),
+        }
+    }
+
+    fn with_rng(decks: u8, penetration: f32, burn_cards: u8, rng: ChaCha12Rng) -> Shoe {
+        let decks = decks.max(1);
+        let penetration = penetration.clamp(0.0, 1.0);
+        let total = decks as usize * 52;
+        let mut shoe = Shoe {
+            cards: PackOfCards::new(),
+            decks,
+            penetration,
+            burn_cards,
+            cut_card: total - (total as f32 * penetration) as usize,
+            seen: [0; 10],
+            rng,
+            in_play: Vec::new(),
+            face_down: Vec::new(),
+        };
+        shoe.shuffle();
+        shoe
+    }
+
+    pub fn decks(&self) -> u8 {
+        self.decks
+    }
+
+    pub fn penetration(&self) -> f32 {
+        self.penetration
+    }
+
+    pub fn burn_cards(&self) -> u8 {
+        self.burn_cards
+    }
+
+    // Number of cards left in the shoe
+    pub fn remaining(&self) -> usize {
+        self.cards.len()
+    }
+
+    pub fn cards(&self) -> &PackOfCards {
+        &self.cards
+    }
+
+    // Decks left in the shoe, the divisor of the true count
+    pub fn decks_remaining(&self) -> f32 {
+        self.cards.len() as f32 / 52.0
+    }
+
+    // Number of cards of each value shown since the shuffle, index 0 for the aces
+    pub fn seen(&self) -> &[u16; 10] {
+        &self.seen
+    }
+
+    // Put every card back in the shoe, shuffle and burn the first cards
+    pub fn shuffle(&mut self) {
+        self.cards = PackOfCards::with_decks(self.decks);
+        self.cards.shuffle(&mut self.rng);
+        self.seen = [0; 10];
+        self.in_play.clear();
+        self.face_down.clear();
+        self.burn();
+    }
+
+    // Burned face down, they are not counted
+    fn burn(&mut self) {
+        for _ in 0..self.burn_cards {
+            let _ = self.cards.pick();
+        }
+    }
+
+    // The cards of the round go to the discard tray, called before each round
+    pub fn end_round(&mut self) {
+        self.in_play.clear();
+        self.face_down.clear();
+    }
+
+    // When the shoe runs out in a round, the cards off the table are shuffled again and
+    // the count starts over from the face-up cards of the table
+    fn reshuffle_without_table(&mut self) {
+        let mut cards = PackOfCards::with_decks(self.decks);
+        for card in &self.in_play {
+            cards.remove(card);
+        }
+        cards.shuffle(&mut self.rng);
+        self.cards = cards;
+        self.seen = [0; 10];
+        let mut hidden = self.face_down.clone();
+        for card in self.in_play.clone() {
+            if let Some(index) = hidden.iter().position(|c| *c == card) {
+                hidden.remove(index);
+            } else {
+                self.count(&card);
+            }
+        }
+        self.burn();
+    }
+
+    // Next card, the shoe is made again from the cards off the table when it is empty
+    fn draw(&mut self) -> Result<Card, BlackjackError> {
+        if self.cards.is_empty() {
+            self.reshuffle_without_table();
+        }
+        let card = self.cards.pick()?;
+        self.in_play.push(card);
+        Ok(card)
+    }
+
+    // Cards of the round already shown, the only ones left of the discard after a reshuffle in the round
+    pub fn shown_in_play(&self) -> Vec<Card> {
+        let mut hidden = self.face_down.clone();
+        self.in_play
+            .iter()
+            .filter(|card| match hidden.iter().position(|c| c == *card) {
+                Some(index) => {
+                    hidden.remove(index);
+                    false
+                }
+                None => true,
+            })
+            .copied()
+            .collect()
+    }
+
+    // True once the cut card has come out
+    pub fn needs_shuffle(&self) -> bool {
+        self.cards.len() <= self.cut_card
+    }
+
+    // Card dealt face up : it is counted as it leaves the shoe
+    pub fn pick(&mut self) -> Result<Card, BlackjackError> {
+        let card = self.draw()?;
+        self.count(&card);
+        Ok(card)
+    }
+
+    // Card dealt face down, counted once turned over with `reveal`
+    pub fn pick_face_down(&mut self) -> Result<Card, BlackjackError> {
+        let card = self.draw()?;
+        self.face_down.push(card);
+        Ok(card)
+    }
+
+    pub fn reveal(&mut self, card: &Card) {
+        if let Some(index) = self.face_down.iter().position(|c| c == card) {
+            self.face_down.remove(index);
+        }
+        self.count(card);
+    }
+
+    // A card of the rank of `card` put on the table face up, to set up a given hand
+    pub fn take(&mut self, card: &Card) -> Result<Card, BlackjackError> {
+        let taken = self
+            .cards
+            .iterator()
+            .iter()
+            .find(|c| c.rank == card.rank)
+            .copied()
+            .ok_or_else(|| {
+                BlackjackError::InvalidArgument(format!(
+                    "no card of rank {:?} left in the shoe",
+                    card.rank
+                ))
+            })?;
+        self.cards.remove(&taken);
+        self.in_play.push(taken);
+        self.count(&taken);
+        Ok(taken)
+    }
+
+    fn count(&mut self, card: &Card) {
+        self.seen[card.value() as usize - 1] += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluation::{Policy, RandomPolicy};
+    use crate::game::GameState;
+    use crate::rules::RuleSet;
+
+    #[test]
+    fn cut_card_comes_out_at_the_penetration() {
+        let total = PackOfCards::with_decks(2).len();
+        // cut card at the end : the whole shoe is dealt but the burnt card
+        let mut shoe = Shoe::new(2, 1.0, 1);
+        assert_eq!(shoe.remaining(), total - 1);
+        let mut dealt = 0;
+        while !shoe.needs_shuffle() {
+            shoe.pick().unwrap();
+            dealt += 1;
+        }
+        assert_eq!(dealt, total - 1);
+        shoe.shuffle();
+        assert_eq!(shoe.remaining(), total - 1);
+        // cut card at the front : reshuffled before every hand
+        assert!(Shoe::new(2, 0.0, 0).needs_shuffle());
+    }
+
+    #[test]
+    fn empty_shoe_is_reshuffled_in_the_round() {
+        let rules = RuleSet {
+            decks: 1,
+            ..RuleSet::default()
+        };
+        // the cut card is the last card : many rounds start with a few cards left
+        let mut game_state = GameState::with_shoe(rules, Shoe::seeded(1, 1.0, 1, 3));
+        let mut policy = RandomPolicy::new(3);
+        for _ in 0..20_000 {
+            game_state.new_round();
+            game_state.deal().unwrap();
+            while game_state.continue_game {
+                let action = policy.choose(&game_state);
+                game_state = game_state.play(action).unwrap();
+            }
+            game_state.croupier_play().unwrap();
+            // every card shown since the shuffle is counted once
+            let seen: u16 = game_state.shoe.seen().iter().sum();
+            assert_eq!(seen as usize, game_state.discard.len());
+            assert!(game_state.shoe.remaining() + game_state.discard.len() <= 52);
+        }
+    }
+
+    #[test]
+    fn given_cards_are_taken_out_of_the_shoe() {
+        let rules = RuleSet {
+            decks: 1,
+            ..RuleSet::default()
+        };
+        let ace = Card::new(Rank::Ace, Suit::Coeur);
+        let game_state = GameState::from_cards(rules.clone(), &[ace, ace], ace).unwrap();
+        let shoe = &game_state.shoe;
+        // the burnt card may be the last ace
+        let aces_left = shoe
+            .cards()
+            .iterator()
+            .iter()
+            .filter(|card| card.rank == Rank::Ace)
+            .count();
+        assert!(aces_left <= 1);
+        assert_eq!(shoe.remaining(), 52 - 3 - shoe.burn_cards() as usize);
+        assert_eq!(shoe.seen()[0], 3);
+        assert_eq!(shoe.seen().iter().sum::<u16>(), 3);
+        assert!(GameState::from_cards(rules, &[ace, ace, ace, ace], ace).is_err());
+    }
+}
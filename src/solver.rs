@@ -0,0 +1,848 @@
+use crate::card::{HandValue, PackOfCards};
+use crate::game::Action;
+use crate::rules::{RuleSet, Surrender};
+use crate::training::{PlayerHandKey, QTable, greedy_action};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::thread;
+
+// Number of cards of each value in a hand : index 0 for the aces, 9 for the tens and faces
+type Counts = [u8; 10];
+
+// Final hand of the dealer : total 0..=21, then bust, then blackjack
+const OUTCOMES: usize = 24;
+const BUST: usize = 22;
+const DEALER_BLACKJACK: usize = 23;
+
+// Rows of the chart, and the dealer up cards in the order of the columns (ace last)
+const HARD_TOTALS: std::ops::RangeInclusive<u8> = 4..=20;
+const SOFT_TOTALS: std::ops::RangeInclusive<u8> = 12..=20;
+pub const UP_CARDS: [u8; 10] = [2, 3, 4, 5, 6, 7, 8, 9, 10, 1];
+
+fn hand_value(counts: &Counts) -> HandValue {
+    let hard = counts
+        .iter()
+        .enumerate()
+        .map(|(index, &n)| (index as u8 + 1).saturating_mul(n))
+        .fold(0, u8::saturating_add);
+    let cards = counts.iter().map(|&n| n as usize).sum();
+    HandValue::new(hard, counts[0] > 0, cards)
+}
+
+fn counts_of(cards: &[u8]) -> Counts {
+    let mut counts = [0; 10];
+    for &value in cards {
+        counts[value as usize - 1] += 1;
+    }
+    counts
+}
+
+fn add(a: &Counts, b: &Counts) -> Counts {
+    let mut sum = *a;
+    for (x, y) in sum.iter_mut().zip(b) {
+        *x += y;
+    }
+    sum
+}
+
+// Expected value of each action per unit bet, after the peek when the dealer peeks.
+// Insurance is the side bet alone, even money is `stand + insurance`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ActionValues {
+    pub stand: f64,
+    pub hit: f64,
+    pub double: Option<f64>,
+    pub split: Option<f64>,
+    pub surrender: Option<f64>,
+    #[serde(default)]
+    pub insurance: Option<f64>,
+}
+
+impl ActionValues {
+    pub fn get(&self, action: Action) -> Option<f64> {
+        match action {
+            Action::Stand => Some(self.stand),
+            Action::Draw => Some(self.hit),
+            Action::Double => self.double,
+            Action::Split => self.split,
+            Action::Surrender => self.surrender,
+            Action::Insurance => self.insurance,
+        }
+    }
+
+    // Best action, and the best one between hit and stand when the others are not allowed (insurance is a side bet)
+    pub fn best(&self) -> (Action, Action) {
+        let fallback = if self.hit > self.stand {
+            Action::Draw
+        } else {
+            Action::Stand
+        };
+        let mut best = fallback;
+        for action in [Action::Double, Action::Split, Action::Surrender] {
+            if let Some(value) = self.get(action)
+                && value > self.get(best).unwrap()
+            {
+                best = action;
+            }
+        }
+        (best, fallback)
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ChartCell {
+    pub best: Action,
+    pub fallback: Action, // played when the best action is not allowed (after a hit for example)
+    pub values: ActionValues,
+}
+
+impl ChartCell {
+    fn from(values: ActionValues) -> ChartCell {
+        let (best, fallback) = values.best();
+        ChartCell {
+            best,
+            fallback,
+            values,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match (self.best, self.fallback) {
+            (Action::Stand, _) => "S",
+            (Action::Draw, _) => "H",
+            (Action::Double, Action::Stand) => "Ds",
+            (Action::Double, _) => "D",
+            (Action::Split, _) => "P",
+            (Action::Surrender, Action::Stand) => "Rs",
+            (Action::Surrender, _) => "R",
+            (Action::Insurance, _) => "I",
+        }
+    }
+}
+
+// One row per hard total, soft total and pair, one column per up card in the order of UP_CARDS.
+// A total is valued by the mean of its two-card hands weighted by how often they are dealt.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StrategyChart {
+    pub rules: RuleSet,
+    pub hard: Vec<Vec<ChartCell>>,
+    pub soft: Vec<Vec<ChartCell>>,
+    pub pairs: Vec<Vec<ChartCell>>,
+}
+
+impl StrategyChart {
+    fn column(up: u8) -> usize {
+        UP_CARDS.iter().position(|&card| card == up).unwrap()
+    }
+
+    pub fn cell(&self, total: u8, soft: bool, pair: Option<u8>, up: u8) -> Option<&ChartCell> {
+        let column = Self::column(up);
+        match pair {
+            Some(value) => self.pairs.get(value as usize - 1).map(|row| &row[column]),
+            None if soft && SOFT_TOTALS.contains(&total) => {
+                Some(&self.soft[(total - SOFT_TOTALS.start()) as usize][column])
+            }
+            None if !soft && HARD_TOTALS.contains(&total) => {
+                Some(&self.hard[(total - HARD_TOTALS.start()) as usize][column])
+            }
+            None => None,
+        }
+    }
+
+    // Action of the chart among the legal ones, 21 and more always stand
+    pub fn action(
+        &self,
+        total: u8,
+        soft: bool,
+        pair: Option<u8>,
+        up: u8,
+        legal: &[Action],
+    ) -> Action {
+        if let Some(cell) = self.cell(total, soft, pair, up)
+            && pair.is_some()
+            && cell.best == Action::Split
+            && legal.contains(&Action::Split)
+        {
+            return Action::Split;
+        }
+        // A pair that is not split is played as its total
+        let action = match self.cell(total, soft, None, up) {
+            Some(cell) if legal.contains(&cell.best) => cell.best,
+            Some(cell) => cell.fallback,
+            None if total >= 21 => Action::Stand,
+            None => Action::Draw,
+        };
+        if legal.contains(&action) || legal.is_empty() {
+            action
+        } else {
+            legal[0]
+        }
+    }
+
+    // Number of visited states of the table whose greedy action is the chart one, out of the states compared
+    pub fn agreement(&self, table: &QTable) -> (usize, usize) {
+        let mut same = 0;
+        let mut compared = 0;
+        for entry in table.states.iter() {
+            let state = entry.key();
+            let values = entry.value();
+            if state.legal_actions().is_empty() || table.state_visits(state) == 0 {
+                continue;
+            }
+            let (total, soft, pair) = match state.player() {
+                PlayerHandKey::Total { total, soft, pair } => (*total, *soft, *pair),
+                PlayerHandKey::Cards(cards) => {
+                    let value = hand_value(&counts_of(cards));
+                    let pair = match cards.as_slice() {
+                        [first, second] if first == second => Some(*first),
+                        _ => None,
+                    };
+                    (value.total(), value.is_soft(), pair)
+                }
+            };
+            let expected = self.action(
+                total,
+                soft,
+                pair,
+                state.croupier_first_card(),
+                state.legal_actions(),
+            );
+            if let Ok(action) = greedy_action(state, values) {
+                compared += 1;
+                if action == expected {
+                    same += 1;
+                }
+            }
+        }
+        (same, compared)
+    }
+}
+
+impl Display for StrategyChart {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}", self.rules)?;
+        let header: String = UP_CARDS
+            .iter()
+            .map(|&up| match up {
+                1 => format!("{:>4}", "A"),
+                _ => format!("{:>4}", up),
+            })
+            .collect();
+        let rows = [
+            (
+                "Hard",
+                &self.hard,
+                HARD_TOTALS.map(|t| t.to_string()).collect::<Vec<_>>(),
+            ),
+            (
+                "Soft",
+                &self.soft,
+                SOFT_TOTALS
+                    .map(|t| match t {
+                        12 => "A,A".to_string(),
+                        _ => format!("A{}", t - 11),
+                    })
+                    .collect(),
+            ),
+            (
+                "Pairs",
+                &self.pairs,
+                (1..=10)
+                    .map(|v| match v {
+                        1 => "A,A".to_string(),
+                        _ => format!("{},{}", v, v),
+                    })
+                    .collect(),
+            ),
+        ];
+        for (title, cells, labels) in rows {
+            writeln!(f, "\n{:<6}{}", title, header)?;
+            for (label, row) in labels.iter().zip(cells.iter()) {
+                let codes: String = row
+                    .iter()
+                    .map(|cell| format!("{:>4}", cell.code()))
+                    .collect();
+                writeln!(f, "{:<6}{}", label, codes)?;
+            }
+        }
+        writeln!(
+            f,
+            "\nS stand, H hit, D double (Ds : else stand), P split, R surrender (Rs : else stand)"
+        )
+    }
+}
+
+/*
+    Expected values over every card that can come out of the shoe, with the
+    composition changing as the cards are dealt. Approximations :
+    - after the peek every card comes from a shoe whose hole card is no blackjack
+    - a split hand only takes the first cards of the other hands out of the shoe
+*/
+pub struct Solver {
+    rules: RuleSet,
+    shoe: [u32; 10],
+    dealer_cache: HashMap<(u8, Counts), [f64; OUTCOMES]>,
+    hit_cache: HashMap<(u8, Counts, Counts), f64>,
+}
+
+impl Solver {
+    pub fn new(rules: RuleSet, pack: &PackOfCards) -> Solver {
+        let mut shoe = [0; 10];
+        for card in pack.iterator() {
+            shoe[card.value() as usize - 1] += 1;
+        }
+        Solver {
+            rules,
+            shoe,
+            dealer_cache: HashMap::new(),
+            hit_cache: HashMap::new(),
+        }
+    }
+
+    // Solver for a fresh shoe of `rules.decks` decks
+    pub fn with_rules(rules: RuleSet) -> Solver {
+        let pack = PackOfCards::with_decks(rules.decks);
+        Solver::new(rules, &pack)
+    }
+
+    // Cards left in the shoe once `removed` and the up card are out
+    fn remaining(&self, up: u8, removed: &Counts) -> [u32; 10] {
+        let mut remaining = self.shoe;
+        for (count, &out) in remaining.iter_mut().zip(removed) {
+            *count = count.saturating_sub(out as u32);
+        }
+        remaining[up as usize - 1] = remaining[up as usize - 1].saturating_sub(1);
+        remaining
+    }
+
+    // Next card of the shoe, whatever the hole card
+    fn unconditioned_probabilities(&self, up: u8, removed: &Counts) -> [f64; 10] {
+        let remaining = self.remaining(up, removed);
+        let total: u32 = remaining.iter().sum();
+        let mut probabilities = [0.0; 10];
+        if total > 0 {
+            for (p, &count) in probabilities.iter_mut().zip(&remaining) {
+                *p = count as f64 / total as f64;
+            }
+        }
+        probabilities
+    }
+
+    // Next card once the peek ruled out the hole card value `x`, out of `n` cards with `n_x` of it :
+    // n_c (n - n_x - [c != x]) / ((n - 1)(n - n_x))
+    fn draw_probabilities(&self, up: u8, removed: &Counts) -> [f64; 10] {
+        let unconditioned = self.unconditioned_probabilities(up, removed);
+        let Some(x) = self.excluded(up) else {
+            return unconditioned;
+        };
+        let remaining = self.remaining(up, removed);
+        let n = remaining.iter().sum::<u32>() as f64;
+        let n_x = remaining[x] as f64;
+        if n <= 1.0 || n == n_x {
+            return unconditioned;
+        }
+        let mut probabilities = [0.0; 10];
+        for (c, (p, &count)) in probabilities.iter_mut().zip(&remaining).enumerate() {
+            let others = if c == x { n - n_x } else { n - n_x - 1.0 };
+            *p = count as f64 * others / ((n - 1.0) * (n - n_x));
+        }
+        probabilities
+    }
+
+    // The dealer looks at his hole card and the round ends on a blackjack
+    fn peeks(&self, up: u8) -> bool {
+        self.rules.dealer_peek && !self.rules.no_hole_card && (up == 1 || up == 10)
+    }
+
+    // Value of the hole card ruled out by the peek, as an index of Counts
+    fn excluded(&self, up: u8) -> Option<usize> {
+        match (self.peeks(up), up) {
+            (true, 1) => Some(9),
+            (true, _) => Some(0),
+            _ => None,
+        }
+    }
+
+    // Probability that the hole card gives a blackjack to the dealer
+    fn dealer_blackjack_probability(&self, up: u8, removed: &Counts) -> f64 {
+        let probabilities = self.unconditioned_probabilities(up, removed);
+        match up {
+            1 => probabilities[9],
+            10 => probabilities[0],
+            _ => 0.0,
+        }
+    }
+
+    fn dealer_outcomes(&mut self, up: u8, removed: &Counts) -> [f64; OUTCOMES] {
+        if let Some(outcomes) = self.dealer_cache.get(&(up, *removed)) {
+            return *outcomes;
+        }
+        let mut remaining = self.remaining(up, removed);
+        let mut dealer = [0; 10];
+        dealer[up as usize - 1] = 1;
+        let excluded = self.excluded(up);
+        let mut cache = HashMap::new();
+        let outcomes = dealer_draw(
+            &self.rules,
+            &mut remaining,
+            &mut dealer,
+            excluded,
+            &mut cache,
+        );
+        self.dealer_cache.insert((up, *removed), outcomes);
+        outcomes
+    }
+
+    // Blackjack of the player : paid at once, a push against a dealer blackjack
+    fn natural(&self, up: u8, hand: &Counts) -> f64 {
+        let p = self.dealer_blackjack_probability(up, hand);
+        self.rules.blackjack_payout.ratio() as f64 * (1.0 - p)
+    }
+
+    // Insurance pays 2 to 1 for half the bet
+    fn insurance(&self, up: u8, hand: &Counts) -> Option<f64> {
+        if up != 1 || !self.rules.insurance {
+            return None;
+        }
+        let p = self.dealer_blackjack_probability(up, hand);
+        Some(1.5 * p - 0.5)
+    }
+
+    fn stand(&mut self, up: u8, hand: &Counts, extra: &Counts) -> f64 {
+        let value = hand_value(hand);
+        if value.is_bust() {
+            return -1.0;
+        }
+        let outcomes = self.dealer_outcomes(up, &add(hand, extra));
+        let total = value.total() as usize;
+        let mut ev = outcomes[BUST] - outcomes[DEALER_BLACKJACK];
+        for (dealer, &p) in outcomes.iter().enumerate().take(22) {
+            if total > dealer {
+                ev += p;
+            } else if total < dealer {
+                ev -= p;
+            }
+        }
+        ev
+    }
+
+    fn hit(&mut self, up: u8, hand: &Counts, extra: &Counts) -> f64 {
+        if let Some(&ev) = self.hit_cache.get(&(up, *hand, *extra)) {
+            return ev;
+        }
+        let probabilities = self.draw_probabilities(up, &add(hand, extra));
+        let mut ev = 0.0;
+        for (index, &p) in probabilities.iter().enumerate() {
+            if p == 0.0 {
+                continue;
+            }
+            let mut next = *hand;
+            next[index] += 1;
+            let value = hand_value(&next);
+            ev += p * if value.is_bust() {
+                -1.0
+            } else if value.total() == 21 {
+                self.stand(up, &next, extra)
+            } else {
+                self.stand(up, &next, extra).max(self.hit(up, &next, extra))
+            };
+        }
+        self.hit_cache.insert((up, *hand, *extra), ev);
+        ev
+    }
+
+    // One card then stand, for twice the bet
+    fn double(&mut self, up: u8, hand: &Counts, extra: &Counts) -> f64 {
+        let probabilities = self.draw_probabilities(up, &add(hand, extra));
+        let mut ev = 0.0;
+        for (index, &p) in probabilities.iter().enumerate() {
+            if p == 0.0 {
+                continue;
+            }
+            let mut next = *hand;
+            next[index] += 1;
+            ev += p * self.stand(up, &next, extra);
+        }
+        2.0 * ev
+    }
+
+    // Best play of a split hand of two cards, the first cards of the `hands - 1` other hands out of the shoe
+    fn split_hand(&mut self, up: u8, value: u8, card: u8, hands: u8) -> f64 {
+        let hand = counts_of(&[value, card]);
+        let mut extra = [0; 10];
+        extra[value as usize - 1] = hands - 1;
+        let stand = self.stand(up, &hand, &extra);
+        let total = hand_value(&hand);
+        if (value == 1 && !self.rules.hit_split_aces) || total.total() == 21 {
+            return stand;
+        }
+        let mut best = stand.max(self.hit(up, &hand, &extra));
+        if self.rules.double_after_split && self.rules.can_double(&total) {
+            best = best.max(self.double(up, &hand, &extra));
+        }
+        best
+    }
+
+    // Split hands played one after the other, `waiting` of the `hands` still need their second card.
+    // A pair card is split again when allowed and worth it, always for aces that cannot be hit.
+    fn split_hands(
+        &mut self,
+        up: u8,
+        value: u8,
+        hands: u8,
+        waiting: u8,
+        cache: &mut HashMap<(u8, u8), f64>,
+    ) -> f64 {
+        if let Some(&ev) = cache.get(&(hands, waiting)) {
+            return ev;
+        }
+        let mut split_cards = [0; 10];
+        split_cards[value as usize - 1] = hands;
+        let probabilities = self.draw_probabilities(up, &split_cards);
+        let next = if waiting == 0 {
+            0.0
+        } else {
+            self.split_hands(up, value, hands, waiting - 1, cache)
+        };
+        let can_resplit = hands <= self.rules.max_splits && (value != 1 || self.rules.resplit_aces);
+        let forced = value == 1 && !self.rules.hit_split_aces;
+        let mut ev = 0.0;
+        for (index, &p) in probabilities.iter().enumerate() {
+            if p == 0.0 {
+                continue;
+            }
+            let card = index as u8 + 1;
+            let play = self.split_hand(up, value, card, hands) + next;
+            ev += p * if card == value && can_resplit {
+                let resplit = self.split_hands(up, value, hands + 1, waiting + 1, cache);
+                if forced { resplit } else { play.max(resplit) }
+            } else {
+                play
+            };
+        }
+        cache.insert((hands, waiting), ev);
+        ev
+    }
+
+    // The first hand gets its second card, the other one waits
+    fn split(&mut self, up: u8, value: u8) -> f64 {
+        self.split_hands(up, value, 2, 1, &mut HashMap::new())
+    }
+
+    fn surrender(&self, up: u8, hand: &Counts) -> Option<f64> {
+        let p = self.dealer_blackjack_probability(up, hand);
+        match (self.rules.surrender, self.peeks(up)) {
+            (Surrender::None, _) => None,
+            (Surrender::Late, true) => Some(-0.5),
+            // Late surrender loses the whole bet against a blackjack
+            (Surrender::Late, false) => Some(-0.5 * (1.0 - p) - p),
+            // Before the peek : the half bet is given up whatever the hole card
+            (Surrender::Early, true) if p < 1.0 => Some((-0.5 + p) / (1.0 - p)),
+            (Surrender::Early, true) => None,
+            (Surrender::Early, false) => Some(-0.5),
+        }
+    }
+
+    // Values of the actions for the hand made of `cards` (card values, ace = 1) against `up`
+    pub fn values(&mut self, cards: &[u8], up: u8) -> ActionValues {
+        let hand = counts_of(cards);
+        let none = [0; 10];
+        let value = hand_value(&hand);
+        let first_decision = cards.len() == 2;
+        let insurance = if first_decision {
+            self.insurance(up, &hand)
+        } else {
+            None
+        };
+        if value.is_blackjack() {
+            // only stand, or even money with the insurance
+            return ActionValues {
+                stand: self.natural(up, &hand),
+                hit: -1.0,
+                double: None,
+                split: None,
+                surrender: None,
+                insurance,
+            };
+        }
+        let double = if first_decision && self.rules.can_double(&value) {
+            Some(self.double(up, &hand, &none))
+        } else {
+            None
+        };
+        let split = match cards {
+            [first, second] if first == second && self.rules.max_splits > 0 => {
+                Some(self.split(up, *first))
+            }
+            _ => None,
+        };
+        let surrender = if first_decision {
+            self.surrender(up, &hand)
+        } else {
+            None
+        };
+        ActionValues {
+            stand: self.stand(up, &hand, &none),
+            hit: if value.total() >= 21 {
+                -1.0
+            } else {
+                self.hit(up, &hand, &none)
+            },
+            double,
+            split,
+            surrender,
+            insurance,
+        }
+    }
+
+    // Probability of being dealt these two cards
+    fn deal_probability(&self, first: u8, second: u8) -> f64 {
+        let total: u32 = self.shoe.iter().sum();
+        let a = self.shoe[first as usize - 1] as f64;
+        let b = self.shoe[second as usize - 1] as f64 - if first == second { 1.0 } else { 0.0 };
+        let p = a * b.max(0.0) / (total as f64 * (total as f64 - 1.0));
+        if first == second { p } else { 2.0 * p }
+    }
+
+    // Mean of the values of several hands weighted by their probability
+    fn row_cell(&mut self, hands: &[(u8, u8)], up: u8) -> ChartCell {
+        let mut weight = 0.0;
+        let mut mean = ActionValues {
+            stand: 0.0,
+            hit: 0.0,
+            double: Some(0.0),
+            split: None,
+            surrender: Some(0.0),
+            insurance: None,
+        };
+        for &(first, second) in hands {
+            let p = self.deal_probability(first, second).max(f64::MIN_POSITIVE);
+            let mut values = self.values(&[first, second], up);
+            values.split = None; // played as a total
+            weight += p;
+            mean.stand += p * values.stand;
+            mean.hit += p * values.hit;
+            mean.double = mean.double.zip(values.double).map(|(m, v)| m + p * v);
+            mean.surrender = mean.surrender.zip(values.surrender).map(|(m, v)| m + p * v);
+        }
+        mean.stand /= weight;
+        mean.hit /= weight;
+        mean.double = mean.double.map(|m| m / weight);
+        mean.surrender = mean.surrender.map(|m| m / weight);
+        ChartCell::from(mean)
+    }
+
+    // Cells of one up card : hard totals, soft totals and pairs
+    fn column(&mut self, up: u8) -> [Vec<ChartCell>; 3] {
+        let hard = HARD_TOTALS
+            .map(|total| {
+                let hands: Vec<(u8, u8)> = (2..=10)
+                    .flat_map(|first| (first..=10).map(move |second| (first, second)))
+                    .filter(|(first, second)| first + second == total)
+                    .collect();
+                self.row_cell(&hands, up)
+            })
+            .collect();
+        let soft = SOFT_TOTALS
+            .map(|total| self.row_cell(&[(1, total - 11)], up))
+            .collect();
+        let pairs = (1..=10)
+            .map(|value| ChartCell::from(self.values(&[value, value], up)))
+            .collect();
+        [hard, soft, pairs]
+    }
+
+    // The up cards are independent, each one is solved in its own thread
+    pub fn chart(&self) -> StrategyChart {
+        let columns: Vec<[Vec<ChartCell>; 3]> = thread::scope(|scope| {
+            let handles: Vec<_> = UP_CARDS
+                .iter()
+                .map(|&up| {
+                    scope.spawn(move || {
+                        let mut solver = Solver {
+                            rules: self.rules.clone(),
+                            shoe: self.shoe,
+                            dealer_cache: HashMap::new(),
+                            hit_cache: HashMap::new(),
+                        };
+                        solver.column(up)
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect()
+        });
+
+        // Columns to rows
+        let mut rows: [Vec<Vec<ChartCell>>; 3] = Default::default();
+        for column in columns {
+            for (kind, cells) in column.into_iter().enumerate() {
+                rows[kind].resize(cells.len(), Vec::new());
+                for (row, cell) in rows[kind].iter_mut().zip(cells) {
+                    row.push(cell);
+                }
+            }
+        }
+        let [hard, soft, pairs] = rows;
+        StrategyChart {
+            rules: self.rules.clone(),
+            hard,
+            soft,
+            pairs,
+        }
+    }
+}
+
+// Probabilities of the final hands of the dealer, `excluded` is the hole card value ruled out by the peek
+fn dealer_draw(
+    rules: &RuleSet,
+    remaining: &mut [u32; 10],
+    dealer: &mut Counts,
+    excluded: Option<usize>,
+    cache: &mut HashMap<u64, [f64; OUTCOMES]>,
+) -> [f64; OUTCOMES] {
+    let key = dealer
+        .iter()
+        .fold(0u64, |key, &count| (key << 5) | count as u64);
+    if let Some(outcomes) = cache.get(&key) {
+        return *outcomes;
+    }
+    let value = hand_value(dealer);
+    let mut outcomes = [0.0; OUTCOMES];
+    if value.is_blackjack() {
+        outcomes[DEALER_BLACKJACK] = 1.0;
+    } else if value.is_bust() {
+        outcomes[BUST] = 1.0;
+    } else if !rules.croupier_must_draw(&value) {
+        outcomes[value.total() as usize] = 1.0;
+    } else {
+        let hole_card = value_cards(dealer) == 1;
+        let skip = if hole_card { excluded } else { None };
+        let total: u32 = remaining
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| Some(*index) != skip)
+            .map(|(_, &count)| count)
+            .sum();
+        if total == 0 {
+            // Empty shoe : the dealer keeps his hand
+            outcomes[value.total() as usize] = 1.0;
+        }
+        for index in 0..10 {
+            if Some(index) == skip || remaining[index] == 0 {
+                continue;
+            }
+            let p = remaining[index] as f64 / total as f64;
+            remaining[index] -= 1;
+            dealer[index] += 1;
+            let next = dealer_draw(rules, remaining, dealer, excluded, cache);
+            dealer[index] -= 1;
+            remaining[index] += 1;
+            for (outcome, x) in outcomes.iter_mut().zip(next) {
+                *outcome += p * x;
+            }
+        }
+    }
+    cache.insert(key, outcomes);
+    outcomes
+}
+
+fn value_cards(counts: &Counts) -> u8 {
+    counts.iter().sum()
+}
+
+// Basic strategy of a rule set for a fresh shoe
+pub fn solve(rules: &RuleSet) -> StrategyChart {
+    Solver::with_rules(rules.clone()).chart()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn best(rules: RuleSet, cards: &[u8], up: u8) -> Action {
+        Solver::with_rules(rules).values(cards, up).best().0
+    }
+
+    #[test]
+    fn dealer_outcomes_sum_to_one() {
+        let mut solver = Solver::with_rules(RuleSet::default());
+        for up in UP_CARDS {
+            let outcomes = solver.dealer_outcomes(up, &counts_of(&[10, 6]));
+            assert!((outcomes.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+            // Below 17 the dealer always draws
+            assert!(outcomes[..17].iter().all(|&p| p == 0.0));
+        }
+    }
+
+    #[test]
+    fn basic_strategy_decisions() {
+        assert_eq!(best(RuleSet::default(), &[10, 6], 10), Action::Draw);
+        assert_eq!(best(RuleSet::default(), &[10, 3], 2), Action::Stand);
+        assert_eq!(best(RuleSet::default(), &[6, 5], 6), Action::Double);
+        assert_eq!(best(RuleSet::default(), &[8, 8], 6), Action::Split);
+        let rules = RuleSet {
+            surrender: Surrender::Late,
+            ..RuleSet::default()
+        };
+        assert_eq!(best(rules, &[10, 6], 10), Action::Surrender);
+    }
+
+    #[test]
+    fn stand_on_20_wins_against_a_dealer_six() {
+        let values = Solver::with_rules(RuleSet::default()).values(&[10, 10], 6);
+        assert!(values.stand > 0.6);
+        assert!(values.hit < values.stand);
+        assert!(values.split.unwrap() < values.stand);
+    }
+
+    #[test]
+    fn naturals_are_paid_at_once_and_push_against_a_dealer_blackjack() {
+        let mut solver = Solver::with_rules(RuleSet::default());
+        let values = solver.values(&[1, 10], 6);
+        assert_eq!(values.stand, 1.5);
+        assert_eq!(values.insurance, None);
+        // 6 decks : 95 tens left out of 309 cards under the ace
+        let values = solver.values(&[1, 10], 1);
+        assert!((values.stand - 1.5 * 214.0 / 309.0).abs() < 1e-12);
+        // even money is worth one bet whatever the hole card
+        assert!((values.stand + values.insurance.unwrap() - 1.0).abs() < 1e-12);
+        // the peek leaves aces more likely under a ten
+        let draws = solver.draw_probabilities(10, &counts_of(&[10, 6]));
+        assert!((draws.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+        assert!(draws[0] > solver.unconditioned_probabilities(10, &counts_of(&[10, 6]))[0]);
+    }
+
+    #[test]
+    fn split_values_follow_the_split_rules() {
+        // one deck, the trees of the split hands are smaller
+        let split = |rules: RuleSet, value: u8, up: u8| {
+            let rules = RuleSet { decks: 1, ..rules };
+            Solver::with_rules(rules).split(up, value)
+        };
+        let one_split = RuleSet {
+            max_splits: 1,
+            ..RuleSet::default()
+        };
+        assert!(split(RuleSet::default(), 8, 6) > split(one_split, 8, 6));
+        let no_das = RuleSet {
+            double_after_split: false,
+            ..RuleSet::default()
+        };
+        assert!(split(RuleSet::default(), 9, 6) > split(no_das, 9, 6));
+        let hit_aces = RuleSet {
+            hit_split_aces: true,
+            resplit_aces: true,
+            ..RuleSet::default()
+        };
+        assert!(split(hit_aces, 1, 6) > split(RuleSet::default(), 1, 6));
+        let resplit_aces = RuleSet {
+            resplit_aces: true,
+            ..RuleSet::default()
+        };
+        assert!(split(resplit_aces, 1, 6) > split(RuleSet::default(), 1, 6));
+    }
+}
@@ -0,0 +1,243 @@
+use crate::card::PackOfCards;
+use crate::error::BlackjackError;
+use crate::evaluation::{Policy, RunningStats};
+use crate::game::GameState;
+use crate::rules::RuleSet;
+use crate::shoe::Shoe;
+use std::fmt::Display;
+
+pub const MAX_SEATS: usize = 7;
+
+// One place at the table : its policy, its hands of the round and what it has won
+pub struct Seat<'a> {
+    pub policy: Box<dyn Policy + 'a>,
+    pub state: GameState, // holds the shoe of the table only while the seat plays, an empty one else
+    pub bet: f32,
+    pub results: RunningStats, // win of each round
+}
+
+// 1 to 7 seats dealt in the casino order from one shoe, played in turn before the dealer plays once
+pub struct Table<'a> {
+    pub rules: RuleSet,
+    pub dealer: GameState, // shoe, discard and dealer cards shared by the seats
+    pub seats: Vec<Seat<'a>>,
+}
+
+impl<'a> Table<'a> {
+    // A seat for each policy, the shoe is shuffled with `seed` when there is one
+    pub fn new(
+        rules: RuleSet,
+        policies: Vec<Box<dyn Policy + 'a>>,
+        bet: f32,
+        seed: Option<u64>,
+    ) -> Result<Table<'a>, BlackjackError> {
+        if policies.is_empty() || policies.len() > MAX_SEATS {
+            return Err(BlackjackError::InvalidArgument(format!(
+                "a table has 1 to {} seats, not {}",
+                MAX_SEATS,
+                policies.len()
+            )));
+        }
+        let dealer = match seed {
+            Some(seed) => GameState::seeded(rules.clone(), seed),
+            None => GameState::with_rules(rules.clone()),
+        };
+        let seats = policies
+            .into_iter()
+            .map(|policy| Seat {
+                policy,
+                state: GameState::with_shoe(rules.clone(), Shoe::empty()),
+                bet,
+                results: RunningStats::default(),
+            })
+            .collect();
+        Ok(Table {
+            rules,
+            dealer,
+            seats,
+        })
+    }
+
+    // Give the shoe and the discard to a seat, or take them back
+    fn exchange_shoe(&mut self, seat: usize) {
+        let state = &mut self.seats[seat].state;
+        std::mem::swap(&mut self.dealer.shoe, &mut state.shoe);
+        std::mem::swap(&mut self.dealer.discard, &mut state.discard);
+    }
+
+    // First two cards of every seat and of the dealer
+    pub fn deal(&mut self) -> Result<(), BlackjackError> {
+        self.dealer.new_round();
+        for seat in &mut self.seats {
+            seat.state.new_round();
+            seat.state.bet = seat.bet;
+        }
+        for round in 0..2 {
+            for seat in 0..self.seats.len() {
+                let card = self.dealer.draw_card()?;
+                self.seats[seat].state.hands[0].cards.add_card(card);
+            }
+            if round == 0 {
+                let up_card = self.dealer.draw_card()?;
+                self.dealer.croupier_cards.add_card(up_card);
+            }
+        }
+        if !self.rules.no_hole_card {
+            let hole_card = self.dealer.draw_face_down()?;
+            self.dealer.croupier_cards.add_card(hole_card);
+        }
+        for seat in &mut self.seats {
+            seat.state.croupier_cards = self.dealer.croupier_cards.clone();
+            seat.state.after_deal();
+        }
+        Ok(())
+    }
+
+    // The seat plays all its hands with the shoe of the table
+    pub fn play_seat(&mut self, seat: usize) -> Result<(), BlackjackError> {
+        self.exchange_shoe(seat);
+        let mut result = Ok(());
+        let Seat { policy, state, .. } = &mut self.seats[seat];
+        while state.continue_game {
+            let action = policy.choose(state);
+            match state.play(action) {
+                Ok(new_state) => *state = new_state,
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        self.exchange_shoe(seat);
+        result
+    }
+
+    // The dealer plays once for every seat, then each seat is paid against his cards
+    pub fn settle(&mut self) -> Result<Vec<f32>, BlackjackError> {
+        self.dealer.hands = self
+            .seats
+            .iter()
+            .filter(|seat| !seat.state.surrender)
+            .flat_map(|seat| seat.state.hands.clone())
+            .collect();
+        self.dealer.croupier_play()?;
+
+        let mut results = Vec::with_capacity(self.seats.len());
+        for seat in &mut self.seats {
+            seat.state.croupier_cards = self.dealer.croupier_cards.clone();
+            let result = seat.state.results(seat.bet);
+            seat.results.add(result as f64);
+            results.push(result);
+        }
+        Ok(results)
+    }
+
+    pub fn play_round(&mut self) -> Result<Vec<f32>, BlackjackError> {
+        self.deal()?;
+        for seat in 0..self.seats.len() {
+            self.play_seat(seat)?;
+        }
+        self.settle()
+    }
+
+    pub fn croupier_cards(&self) -> &PackOfCards {
+        &self.dealer.croupier_cards
+    }
+}
+
+// Results of each seat, the first seat gets its cards first
+impl Display for Table<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:>4} {:<16} {:>10} {:>10} {:>10}",
+            "seat", "policy", "rounds", "mean", "SD"
+        )?;
+        for (index, seat) in self.seats.iter().enumerate() {
+            writeln!(
+                f,
+                "{:>4} {:<16} {:>10} {:>+10.4} {:>10.4}",
+                index + 1,
+                seat.policy.name(),
+                seat.results.count,
+                seat.results.mean,
+                seat.results.std_dev()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::evaluation::{NeverBust, RandomPolicy};
+
+    #[test]
+    fn one_seat_plays_like_a_single_game() {
+        let rules = RuleSet::default();
+        let mut table = Table::new(
+            rules.clone(),
+            vec![Box::new(RandomPolicy::new(3))],
+            1.0,
+            Some(5),
+        )
+        .unwrap();
+        let mut policy = RandomPolicy::new(3);
+        let mut game_state = GameState::seeded(rules, 5);
+        for _ in 0..2_000 {
+            game_state.new_round();
+            game_state.deal().unwrap();
+            while game_state.continue_game {
+                let action = policy.choose(&game_state);
+                game_state = game_state.play(action).unwrap();
+            }
+            game_state.croupier_play().unwrap();
+            assert_eq!(table.play_round().unwrap(), vec![game_state.results(1.0)]);
+        }
+    }
+
+    #[test]
+    fn seats_share_the_shoe() {
+        assert!(Table::new(RuleSet::default(), Vec::new(), 1.0, None).is_err());
+        let policies: Vec<Box<dyn Policy>> = (0..MAX_SEATS)
+            .map(|_| Box::new(NeverBust) as Box<dyn Policy>)
+            .collect();
+        let mut table = Table::new(RuleSet::default(), policies, 1.0, Some(2)).unwrap();
+        // first round of the shoe : every card shown is in a hand of the table
+        table.play_round().unwrap();
+        let in_hands: usize = table
+            .seats
+            .iter()
+            .flat_map(|seat| &seat.state.hands)
+            .map(|hand| hand.cards.len())
+            .sum::<usize>()
+            + table.croupier_cards().len();
+        assert_eq!(table.dealer.discard.len(), in_hands);
+        let seen: u16 = table.dealer.shoe.seen().iter().sum();
+        assert_eq!(seen as usize, in_hands);
+        assert!(table.seats.iter().all(|seat| seat.results.count == 1));
+        // out of their turn the seats hold an empty shoe that cannot deal
+        for seat in &mut table.seats {
+            assert_eq!(seat.state.shoe.remaining(), 0);
+            assert!(seat.state.draw_card().is_err());
+        }
+    }
+
+    #[test]
+    fn seven_seats_on_one_deck() {
+        let rules = RuleSet {
+            decks: 1,
+            ..RuleSet::default()
+        };
+        let policies: Vec<Box<dyn Policy>> = (0..MAX_SEATS)
+            .map(|seat| Box::new(RandomPolicy::new(seat as u64)) as Box<dyn Policy>)
+            .collect();
+        let mut table = Table::new(rules, policies, 1.0, Some(1)).unwrap();
+        for _ in 0..2_000 {
+            table.play_round().unwrap();
+            let seen: u16 = table.dealer.shoe.seen().iter().sum();
+            assert_eq!(seen as usize, table.dealer.discard.len());
+        }
+    }
+}
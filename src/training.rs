@@ -1,265 +1,1007 @@
+use crate::checkpoint::RunState;
+use crate::counting::{CountingSystem, HI_LO};
+use crate::env::{BlackjackEnv, Environment};
+use crate::error::BlackjackError;
 use crate::game::*;
-use core::panic;
+use crate::rules::RuleSet;
+use crate::schedule::{Exploration, LearningRate};
 use dashmap::DashMap;
 use rand::Rng;
 use rand::seq::SliceRandom;
-use rand::thread_rng;
-use serde::Serialize;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
 use std::fs::File;
-use std::io::Write;
+use std::io::{BufReader, BufWriter, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
 
 const ALPHA: f32 = 0.1;
-const GAMMA: f32 = 0.5;
+const GAMMA: f32 = 1.0; // a game of blackjack is short, the money won at the end is not discounted
+
+// Version of the JSON layout written by QTable::save, bumped on breaking changes
+pub const FORMAT_VERSION: u32 = 8;
+
+// One decision of a game : the reward is 0 until the last decision of a hand, which has no next state
+#[derive(Clone, Debug)]
+pub struct Transition {
+    pub state: State,
+    pub action: Action,
+    pub reward: f32,
+    pub next_state: Option<State>,
+    pub next_action: Option<Action>, // action played in the next state
+}
+
+// How the hand of the player is described in a state
+#[derive(Eq, Hash, PartialEq, PartialOrd, Ord, Clone, Serialize, Deserialize, Debug)]
+pub enum PlayerHandKey {
+    // best total, if an ace counts 11, value of the cards when the hand is a pair
+    Total {
+        total: u8,
+        soft: bool,
+        pair: Option<u8>,
+    },
+    // sorted values of every card of the hand
+    Cards(Vec<u8>),
+}
 
-#[derive(Eq, Hash, PartialEq, Clone, Serialize, Debug)]
+#[derive(Eq, Hash, PartialEq, PartialOrd, Ord, Clone, Serialize, Deserialize, Debug)]
 pub struct State {
-    player_cards: Vec<u8>,
+    player: PlayerHandKey,
     croupier_first_card: u8,
     insurance: bool,
+    true_count: Option<i8>, // only filled by the count-augmented encoder
+    legal_actions: Vec<Action>,
 }
 
 impl State {
+    // Exact composition of the hand, see StateEncoder::Composition
     pub fn from(game_state: &GameState) -> State {
-        let mut player_cards = Vec::new();
-        for card in game_state.get_player_cards().iterator() {
-            player_cards.push(card.unwrap().clone());
-        }
-        player_cards.sort();
+        StateEncoder::Composition.encode(game_state)
+    }
+
+    pub fn player(&self) -> &PlayerHandKey {
+        &self.player
+    }
+
+    pub fn croupier_first_card(&self) -> u8 {
+        self.croupier_first_card
+    }
+
+    // Actions the player can choose in this state, given by GameState::legal_actions
+    pub fn legal_actions(&self) -> &[Action] {
+        &self.legal_actions
+    }
+}
+
+/*
+    How a game is turned into a state, fewer states learn faster:
+    - Classic : total, soft or hard, pair and dealer up card
+    - Composition : every card of the hand
+    - CountAugmented : Classic plus the Hi-Lo true count
+*/
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StateEncoder {
+    Classic,
+    #[default]
+    Composition,
+    CountAugmented,
+}
+
+// True counts further than this from 0 are put together
+pub const MAX_TRUE_COUNT: f32 = 10.0;
+
+impl StateEncoder {
+    pub fn encode(&self, game_state: &GameState) -> State {
+        let cards = game_state.get_player_cards();
+        let player = match self {
+            StateEncoder::Classic | StateEncoder::CountAugmented => {
+                let value = cards.value();
+                let pair = match (cards.get_card(0), cards.get_card(1)) {
+                    (Some(first), Some(second))
+                        if cards.len() == 2 && first.value() == second.value() =>
+                    {
+                        Some(first.value())
+                    }
+                    _ => None,
+                };
+                PlayerHandKey::Total {
+                    total: value.total(),
+                    soft: value.is_soft(),
+                    pair,
+                }
+            }
+            StateEncoder::Composition => {
+                let mut player_cards = Vec::new();
+                for card in cards.iterator() {
+                    player_cards.push(card.value());
+                }
+                player_cards.sort();
+                PlayerHandKey::Cards(player_cards)
+            }
+        };
+        let true_count = match self {
+            StateEncoder::CountAugmented => Some(
+                HI_LO
+                    .true_count(&game_state.shoe)
+                    .round()
+                    .clamp(-MAX_TRUE_COUNT, MAX_TRUE_COUNT) as i8,
+            ),
+            _ => None,
+        };
 
         State {
-            player_cards: player_cards,
-            croupier_first_card: *game_state.get_croupier_first_card().unwrap().unwrap(),
+            player,
+            croupier_first_card: game_state.get_croupier_first_card().unwrap().value(),
             insurance: game_state.get_insurance(),
+            true_count,
+            legal_actions: game_state.legal_actions(),
         }
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Metadata {
+    pub format_version: u32,
+    pub rules: RuleSet, // rules the table was trained on
+    pub encoder: StateEncoder,
+    pub algorithm: String, // learning algorithm used to train the table
+    pub learning_rate: LearningRate,
+    pub gamma: f32, // discount factor
+    pub exploration: Exploration,
+    pub initial_value: f32, // value of the actions of a new state, optimistic when above 0
+    pub episodes: u64,      // number of training games played
+    pub seed: u64,          // master seed of the shoes and explorations of the training
+}
+
+impl Default for Metadata {
+    fn default() -> Self {
+        Metadata {
+            format_version: FORMAT_VERSION,
+            rules: RuleSet::default(),
+            encoder: StateEncoder::default(),
+            algorithm: String::from("q-learning"),
+            learning_rate: LearningRate::Constant(ALPHA),
+            gamma: GAMMA,
+            exploration: Exploration::default(),
+            initial_value: 0.0,
+            episodes: 0,
+            seed: 0,
+        }
+    }
+}
+
+// One line of the Q-table : a state and the value of each of its actions
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    state: State,
+    actions: Vec<f32>,
+    #[serde(default)]
+    visits: Vec<u64>, // number of updates of each action
+}
+
+// Layout of the saved file
+#[derive(Serialize, Deserialize)]
+struct QTableFile {
+    metadata: Metadata,
+    states: Vec<Entry>,
+    // only in the checkpoints of a training run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    run: Option<RunState>,
+}
+
+#[derive(Default)]
 pub struct QTable {
     pub states: DashMap<State, Vec<f32>>,
+    pub visits: DashMap<State, Vec<u64>>, // updates of each action, for the schedules based on visits
+    pub metadata: Metadata,
+    games: AtomicU64, // games learned, saved as `metadata.episodes`
+}
+
+impl Clone for QTable {
+    fn clone(&self) -> Self {
+        QTable {
+            states: self.states.clone(),
+            visits: self.visits.clone(),
+            metadata: self.metadata.clone(),
+            games: AtomicU64::new(self.games()),
+        }
+    }
 }
 
 impl QTable {
     pub fn new() -> Self {
+        QTable::default()
+    }
+
+    // Empty table for the given rules, a table should only be trained on games played with them
+    pub fn with_rules(rules: RuleSet) -> Self {
+        QTable::with_encoder(rules, StateEncoder::default())
+    }
+
+    pub fn with_encoder(rules: RuleSet, encoder: StateEncoder) -> Self {
         QTable {
-            states: DashMap::new(),
+            metadata: Metadata {
+                rules,
+                encoder,
+                ..Metadata::default()
+            },
+            ..QTable::default()
         }
     }
 
+    // State of the game as seen by this table
+    pub fn encode(&self, game_state: &GameState) -> State {
+        self.metadata.encoder.encode(game_state)
+    }
+
     pub fn len(&self) -> usize {
         self.states.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+
     pub fn from(other: QTable) -> QTable {
         QTable {
             states: other.states,
+            visits: other.visits,
+            metadata: other.metadata,
+            games: other.games,
         }
     }
 
-    pub fn add_state(&mut self, state: State) {
+    // Number of games learned, including the ones of the training runs before loading
+    pub fn games(&self) -> u64 {
+        self.games.load(Ordering::Relaxed)
+    }
+
+    pub fn record_game(&self) {
+        self.games.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Number of updates made in a state, all actions together
+    pub fn state_visits(&self, state: &State) -> u64 {
+        self.visits
+            .get(state)
+            .map_or(0, |visits| visits.iter().sum())
+    }
+
+    // Counts one more update of (state, action) and returns the learning rate to use for it
+    pub fn next_alpha(&self, state: &State, action: Action) -> f32 {
+        let visits = {
+            let mut visits = self
+                .visits
+                .entry(state.clone())
+                .or_insert_with(|| vec![0; Action::ALL.len()]);
+            visits[action.into_index()] += 1;
+            visits[action.into_index()]
+        };
+        self.metadata.learning_rate.alpha(visits, self.games())
+    }
+
+    // Several threads can add the same state at the same time, the first one wins
+    pub fn add_state(&self, state: State) {
         if self.states.contains_key(&state) {
             return;
         }
-        let actions;
+        self.states
+            .entry(state)
+            .or_insert_with(|| vec![self.metadata.initial_value; Action::ALL.len()]);
+    }
 
-        if state.croupier_first_card == 1 && state.player_cards.len() == 2 && !state.insurance {
-            actions = vec![0.0, 0.0, 0.0, 0.0];
-        } else {
-            actions = vec![0.0, 0.0, 0.0];
-        }
+    pub fn get_best_action<R: Rng + ?Sized>(
+        &self,
+        state: &State,
+        epsilon: f32,
+        rng: &mut R,
+    ) -> Result<Action, BlackjackError> {
+        self.add_state(state.clone());
+        let values = self
+            .states
+            .get(state)
+            .ok_or_else(|| BlackjackError::MissingState(state.clone()))?
+            .clone();
+        select_action(state, &values, epsilon, rng)
+    }
 
-        self.states.insert(state, actions);
+    // Best value among the legal actions of a state, 0 for an unknown state
+    pub fn max_value(&self, state: &State) -> f32 {
+        match self.states.get(state) {
+            Some(values) => state
+                .legal_actions()
+                .iter()
+                .map(|action| values[action.into_index()])
+                .filter(|x| !x.is_nan()) // pour éviter les NaN si besoin
+                .max_by(|a, b| a.partial_cmp(b).unwrap())
+                .unwrap_or(0.0),
+            None => 0.0,
+        }
     }
 
-    pub fn get_best_action(&mut self, state: &State, epsilon: f32) -> Action {
-        let mut rng = rand::thread_rng();
-        let rd: f32 = rng.gen_range(0.0..1.0);
-        if !self.states.contains_key(state) {
-            self.add_state(state.clone());
+    /*
+        Q(s, a) ← Q(s, a) + α × [r + γ × max_a' Q(s', a') - Q(s, a)]
+        The max only looks at the legal actions of s', and there is no
+        bootstrap on the last transition of a game.
+    */
+    pub fn update(&self, transition: &Transition) -> Result<(), BlackjackError> {
+        if !transition.reward.is_finite() {
+            return Err(BlackjackError::InvalidReward(transition.reward));
         }
-        if rd < epsilon
-            && state.croupier_first_card == 1
-            && state.player_cards.len() == 2
-            && !state.insurance
-        {
-            let mut rng_action = thread_rng();
-            let actions_possible = [
-                Action::Draw,
-                Action::Stand,
-                Action::Double,
-                Action::Insurance,
-            ];
-            *actions_possible.choose(&mut rng_action).unwrap()
-        } else if rd < epsilon {
-            let mut rng_action = thread_rng();
-            let actions_possible = [Action::Draw, Action::Stand, Action::Double];
-            *actions_possible.choose(&mut rng_action).unwrap()
-        } else {
-            let vec = self.states.get(state).expect("State should be present");
-            let var = vec
-                .iter()
-                .enumerate()
-                .filter(|&(_, &x)| !x.is_nan()) // pour éviter les NaN si besoin
-                .max_by(|a, b| a.1.partial_cmp(b.1).unwrap());
-
-            let action;
-            match var {
-                Some((index, _value)) => match index {
-                    0 => {
-                        action = Action::Draw;
-                    }
-                    1 => {
-                        action = Action::Stand;
-                    }
-                    2 => {
-                        action = Action::Double;
-                    }
-                    3 => {
-                        action = Action::Insurance;
-                    }
-                    _ => {
-                        panic!("Too many arguments in list of action");
-                    }
-                },
-                None => {
-                    panic!("No value found in the list of actions");
+
+        let td_target = match &transition.next_state {
+            Some(next_state) => {
+                if !self.states.contains_key(next_state) {
+                    return Err(BlackjackError::MissingState(next_state.clone()));
                 }
+                transition.reward + self.metadata.gamma * self.max_value(next_state)
             }
-            drop(vec);
-            action
-        }
+            None => transition.reward,
+        };
+
+        self.update_toward(&transition.state, transition.action, td_target)
     }
 
-    pub fn update(
-        &mut self,
+    // Q(s, a) ← Q(s, a) + α × [target - Q(s, a)], shared by every learning algorithm
+    pub fn update_toward(
+        &self,
         state: &State,
-        next_state: &State,
-        action: &Action,
-        next_action: &Option<Action>,
-        reward: f32,
-    ) {
-        if reward.is_nan() {
-            panic!("Reward is NaN, check your game logic");
-        } else if reward.is_infinite() {
-            panic!("Reward is infinite, check your game logic");
-        } else if reward == 0.0 {
-            return;
+        action: Action,
+        target: f32,
+    ) -> Result<(), BlackjackError> {
+        if !self.states.contains_key(state) {
+            return Err(BlackjackError::MissingState(state.clone()));
+        }
+        let alpha = self.next_alpha(state, action);
+        if let Some(mut curr_vec) = self.states.get_mut(state) {
+            let index = action.into_index();
+            let td_delta = target - curr_vec[index];
+            curr_vec[index] += alpha * td_delta;
+        }
+        Ok(())
+    }
+
+    // Backwards, so the money of a game already reaches its first decisions
+    pub fn learn(&self, episode: &[Transition]) -> Result<(), BlackjackError> {
+        for transition in episode.iter().rev() {
+            self.update(transition)?;
         }
+        Ok(())
+    }
 
-        let td_target: f32;
+    pub fn save(&self, path: &str) -> Result<(), BlackjackError> {
+        self.write(path, None)
+    }
 
-        match next_action {
-            Some(_) => {
-                let next_vec = self
-                    .states
-                    .get(next_state)
-                    .expect("Next state should be present");
+    // The table and the state of the run that trains it, see checkpoint.rs
+    pub fn save_checkpoint(&self, path: &str, run: RunState) -> Result<(), BlackjackError> {
+        self.write(path, Some(run))
+    }
 
-                let best_action = next_vec
-                    .iter()
-                    .enumerate()
-                    .filter(|&(_, &x)| !x.is_nan()) // pour éviter les NaN si besoin
-                    .max_by(|a, b| a.1.partial_cmp(b.1).unwrap())
-                    .unwrap();
+    fn write(&self, path: &str, run: Option<RunState>) -> Result<(), BlackjackError> {
+        let mut states: Vec<Entry> = self
+            .states
+            .iter()
+            .map(|entry| Entry {
+                state: entry.key().clone(),
+                actions: entry.value().clone(),
+                visits: self
+                    .visits
+                    .get(entry.key())
+                    .map_or_else(Vec::new, |visits| visits.clone()),
+            })
+            .collect();
+        // Sorted so that two tables can be compared with a simple diff
+        states.sort_by(|a, b| a.state.cmp(&b.state));
+
+        let file = QTableFile {
+            metadata: Metadata {
+                episodes: self.games(),
+                ..self.metadata.clone()
+            },
+            states,
+            run,
+        };
+        // Written next to the file then renamed : a crash while saving leaves the old file whole
+        let temporary = format!("{}.tmp", path);
+        let output = File::create(&temporary)?;
+        let mut writer = BufWriter::new(&output);
+        serde_json::to_writer_pretty(&mut writer, &file)?;
+        writer.flush()?;
+        drop(writer);
+        output.sync_all()?;
+        std::fs::rename(&temporary, path)?;
+        Ok(())
+    }
 
-                td_target = reward + GAMMA * best_action.1;
+    pub fn load(path: &str) -> Result<QTable, BlackjackError> {
+        QTable::load_checkpoint(path).map(|(table, _)| table)
+    }
 
-                drop(next_vec);
-            }
-            None => {
-                td_target = reward;
+    // A table and, if the file is a checkpoint, the state of its training run
+    pub fn load_checkpoint(path: &str) -> Result<(QTable, Option<RunState>), BlackjackError> {
+        let reader = BufReader::new(File::open(path)?);
+        let file: QTableFile = serde_json::from_reader(reader)?;
+        if file.metadata.format_version != FORMAT_VERSION {
+            return Err(BlackjackError::InvalidTable(format!(
+                "unsupported format version {} (expected {})",
+                file.metadata.format_version, FORMAT_VERSION
+            )));
+        }
+
+        if let Some(run) = &file.run
+            && run.threads.is_empty()
+        {
+            return Err(BlackjackError::InvalidTable(
+                "checkpoint without any training thread".to_string(),
+            ));
+        }
+        // One value and one visit count per action, the updates index them by action
+        if let Some(entry) = file.states.iter().find(|entry| {
+            entry.actions.len() != Action::ALL.len()
+                || !(entry.visits.is_empty() || entry.visits.len() == Action::ALL.len())
+        }) {
+            return Err(BlackjackError::InvalidTable(format!(
+                "state {:?} has {} values and {} visit counts (expected {})",
+                entry.state,
+                entry.actions.len(),
+                entry.visits.len(),
+                Action::ALL.len()
+            )));
+        }
+
+        let states = DashMap::new();
+        let visits = DashMap::new();
+        for entry in file.states {
+            if !entry.visits.is_empty() {
+                visits.insert(entry.state.clone(), entry.visits);
             }
+            states.insert(entry.state, entry.actions);
         }
+        let table = QTable {
+            states,
+            visits,
+            games: AtomicU64::new(file.metadata.episodes),
+            metadata: file.metadata,
+        };
+        Ok((table, file.run))
+    }
 
-        if let Some(mut curr_vec) = self.states.get_mut(state) {
-            let current_q = curr_vec[action.into_index()];
+    // Environment whose observations are the states of this table
+    pub fn environment(&self, game_state: GameState) -> BlackjackEnv {
+        BlackjackEnv::with_game(game_state, self.metadata.encoder)
+    }
 
-            let td_delta = td_target - current_q;
+    // One round of `env`, encoded like the table (see QTable::environment). Each split hand is a
+    // chain of transitions ending with its own money, the split itself gets the money of its hands.
+    pub fn play_episode<E, F>(
+        &self,
+        env: &mut E,
+        mut choose: F,
+    ) -> Result<(Vec<Transition>, f32), BlackjackError>
+    where
+        E: Environment<Observation = State>,
+        F: FnMut(&State) -> Result<Action, BlackjackError>,
+    {
+        let mut state = env.reset(None)?;
+        let mut episode: Vec<Transition> = Vec::new();
+        if env.legal_actions().is_empty() {
+            // decided by the deal, there is nothing to learn
+            let (_, reward, _, _) = env.step(Action::Stand)?;
+            return Ok((episode, reward));
+        }
 
-            curr_vec[action.into_index()] += ALPHA * td_delta;
+        let mut hands = Vec::new(); // hand of each transition
+        let mut steps = Vec::new(); // (current hand, hands) after each step
+        let mut splits = Vec::new(); // (transition, hand, hands after it that are not its own)
+        loop {
+            self.add_state(state.clone());
+            let action = choose(&state)?;
+            if let Some(previous) = episode.last_mut()
+                && previous.next_state.is_some()
+            {
+                previous.next_action = Some(action);
+            }
+            let (next_state, reward, done, info) = env.step(action)?;
+            // no split when the dealer shows a blackjack before it
+            let split = info.hands > steps.last().map_or(1, |&(_, hands)| hands);
+            if split {
+                splits.push((episode.len(), info.hand, info.hands - info.hand - 2));
+            }
+            episode.push(Transition {
+                state,
+                action,
+                reward: 0.0,
+                next_state: (!info.hand_over && !split).then(|| next_state.clone()),
+                next_action: None,
+            });
+            hands.push(info.hand);
+            steps.push((info.current_hand, info.hands));
+            if !done {
+                state = next_state;
+                continue;
+            }
 
-            drop(curr_vec);
-        } else {
-            panic!("State {:?} not found in QTable", state);
+            for (transition, &hand) in episode.iter_mut().zip(&hands) {
+                if transition.next_state.is_none() {
+                    transition.reward = info.hand_rewards[hand];
+                }
+            }
+            // The hands of a split end once only the hands waiting before it are left, or with the round
+            for (index, hand, others) in splits {
+                let end = steps[index..]
+                    .iter()
+                    .find(|&&(current, hands)| hands - current == others)
+                    .map_or(info.hands, |&(current, _)| current);
+                episode[index].reward = info.hand_rewards[hand..end].iter().sum();
+            }
+            return Ok((episode, reward));
         }
     }
 
-    pub fn save(&self, path: &str) -> std::io::Result<()> {
-        let mut map: HashMap<_, _> = HashMap::new();
+    // One game of Q-learning with an epsilon-greedy policy
+    pub fn trainnig_q<E, R>(
+        &self,
+        env: &mut E,
+        epsilon: f32,
+        rng: &mut R,
+    ) -> Result<f32, BlackjackError>
+    where
+        E: Environment<Observation = State>,
+        R: Rng + ?Sized,
+    {
+        let (episode, reward) =
+            self.play_episode(env, |state| self.get_best_action(state, epsilon, rng))?;
+        self.learn(&episode)?;
+        self.record_game();
+        Ok(reward)
+    }
+}
+
+// Epsilon-greedy choice among the legal actions of a state
+pub fn select_action<R: Rng + ?Sized>(
+    state: &State,
+    values: &[f32],
+    epsilon: f32,
+    rng: &mut R,
+) -> Result<Action, BlackjackError> {
+    let rd: f32 = rng.gen_range(0.0..1.0);
+    if rd < epsilon {
+        state
+            .legal_actions()
+            .choose(rng)
+            .copied()
+            .ok_or_else(|| BlackjackError::NoLegalAction(state.clone()))
+    } else {
+        greedy_action(state, values)
+    }
+}
+
+// Legal action with the best value
+pub fn greedy_action(state: &State, values: &[f32]) -> Result<Action, BlackjackError> {
+    state
+        .legal_actions()
+        .iter()
+        .filter(|action| !values[action.into_index()].is_nan()) // pour éviter les NaN si besoin
+        .max_by(|a, b| {
+            values[a.into_index()]
+                .partial_cmp(&values[b.into_index()])
+                .unwrap()
+        })
+        .copied()
+        .ok_or_else(|| BlackjackError::NoLegalAction(state.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::card::{Card, PackOfCards, Rank, Suit};
+    use crate::env::{Info, ObservationSpace};
+    use crate::schedule::Behaviour;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    // Game where the player holds `ranks` against the up card of the dealer
+    fn dealt(ranks: &[Rank], up: Rank) -> GameState {
+        let cards = PackOfCards::from_ranks(ranks);
+        let up_card = Card::new(up, Suit::Coeur);
+        GameState::from_cards(RuleSet::default(), cards.iterator(), up_card).unwrap()
+    }
+
+    #[test]
+    fn encoders_keep_what_they_need_of_the_hand() {
+        let nine_eight = dealt(&[Rank::Nine, Rank::Eight], Rank::Six);
+        let king_seven = dealt(&[Rank::King, Rank::Seven], Rank::Six);
+        let classic = StateEncoder::Classic;
+        assert_eq!(classic.encode(&nine_eight), classic.encode(&king_seven));
+        let composition = StateEncoder::Composition;
+        assert_ne!(
+            composition.encode(&nine_eight),
+            composition.encode(&king_seven)
+        );
+        // a pair is not played like the other 16
+        let pair = classic.encode(&dealt(&[Rank::Eight, Rank::Eight], Rank::Six));
+        assert_eq!(
+            pair.player,
+            PlayerHandKey::Total {
+                total: 16,
+                soft: false,
+                pair: Some(8),
+            }
+        );
+        // only the count-augmented encoder looks at the shoe
+        assert_eq!(classic.encode(&nine_eight).true_count, None);
+        assert!(
+            StateEncoder::CountAugmented
+                .encode(&nine_eight)
+                .true_count
+                .is_some()
+        );
+    }
 
-        for entry in self.states.iter() {
-            map.insert(entry.key().clone(), entry.value().clone());
+    fn state(total: u8, legal_actions: Vec<Action>) -> State {
+        State {
+            player: PlayerHandKey::Total {
+                total,
+                soft: false,
+                pair: None,
+            },
+            croupier_first_card: 10,
+            insurance: false,
+            true_count: None,
+            legal_actions,
         }
+    }
 
-        let mut json_str = String::from("");
-        for (state, action) in map.iter() {
-            json_str.push_str(format!("{:?} : {:?}", state, action).as_str());
-            json_str.push('\n');
+    fn table_with(values: &[(&State, Vec<f32>)]) -> QTable {
+        let table = QTable::new();
+        for (state, actions) in values {
+            table.states.insert((*state).clone(), actions.clone());
         }
-        let json = serde_json::to_string_pretty(&json_str).unwrap();
-        let mut file = File::create(path)?;
-        file.write_all(json.as_bytes())?;
-        Ok(())
+        table
     }
 
-    pub fn trainnig_q(&mut self, game_state: &mut GameState, bet: f32, epsilon: f32) -> f32 {
-        let card = game_state.packet.pick().unwrap();
-        game_state.player_cards.add_card(card.clone());
-        game_state.discard.add_card(card.clone());
+    fn q(table: &QTable, state: &State, action: Action) -> f32 {
+        table.states.get(state).unwrap()[action.into_index()]
+    }
 
-        let card = game_state.packet.pick().unwrap();
-        game_state.player_cards.add_card(card.clone());
-        game_state.discard.add_card(card.clone());
+    #[test]
+    fn terminal_transition_moves_toward_the_payout() {
+        let s = state(20, vec![Action::Draw, Action::Stand]);
+        let table = table_with(&[(&s, vec![0.0; Action::ALL.len()])]);
+
+        table
+            .update(&Transition {
+                state: s.clone(),
+                action: Action::Stand,
+                reward: 1.0,
+                next_state: None,
+                next_action: None,
+            })
+            .unwrap();
+
+        assert!((q(&table, &s, Action::Stand) - ALPHA).abs() < 1e-6);
+        assert_eq!(q(&table, &s, Action::Draw), 0.0);
+    }
 
-        let card = game_state.packet.pick().unwrap();
-        game_state.croupier_cards.add_card(card.clone());
-        game_state.discard.add_card(card.clone());
+    #[test]
+    fn push_updates_toward_zero() {
+        let s = state(18, vec![Action::Draw, Action::Stand]);
+        let mut values = vec![0.0; Action::ALL.len()];
+        values[Action::Stand.into_index()] = 0.5;
+        let table = table_with(&[(&s, values)]);
+
+        table
+            .update(&Transition {
+                state: s.clone(),
+                action: Action::Stand,
+                reward: 0.0,
+                next_state: None,
+                next_action: None,
+            })
+            .unwrap();
+
+        assert!((q(&table, &s, Action::Stand) - 0.5 * (1.0 - ALPHA)).abs() < 1e-6);
+    }
 
-        let mut map: HashMap<usize, (Option<Action>, Option<State>)> = HashMap::new();
-        let mut i = 0;
+    #[test]
+    fn intermediate_transition_bootstraps_on_legal_actions_only() {
+        let s = state(12, vec![Action::Draw, Action::Stand, Action::Double]);
+        // Double has the best value but is not legal with three cards
+        let next = state(17, vec![Action::Draw, Action::Stand]);
+        let mut next_values = vec![0.0; Action::ALL.len()];
+        next_values[Action::Draw.into_index()] = -0.6;
+        next_values[Action::Stand.into_index()] = -0.2;
+        next_values[Action::Double.into_index()] = 5.0;
+        let table = table_with(&[(&s, vec![0.0; Action::ALL.len()]), (&next, next_values)]);
+
+        table
+            .update(&Transition {
+                state: s.clone(),
+                action: Action::Draw,
+                reward: 0.0,
+                next_state: Some(next),
+                next_action: None,
+            })
+            .unwrap();
+
+        assert!((q(&table, &s, Action::Draw) - ALPHA * -0.2).abs() < 1e-6);
+    }
 
-        while game_state.continue_game {
-            let state = State::from(game_state);
-            let action = self.get_best_action(&state, epsilon);
+    #[test]
+    fn gamma_discounts_the_next_state() {
+        let s = state(12, vec![Action::Draw, Action::Stand]);
+        let next = state(20, vec![Action::Draw, Action::Stand]);
+        let mut next_values = vec![0.0; Action::ALL.len()];
+        next_values[Action::Stand.into_index()] = 1.0;
+        let mut table = table_with(&[(&s, vec![0.0; Action::ALL.len()]), (&next, next_values)]);
+        table.metadata.gamma = 0.5;
+
+        table
+            .update(&Transition {
+                state: s.clone(),
+                action: Action::Draw,
+                reward: 0.0,
+                next_state: Some(next),
+                next_action: None,
+            })
+            .unwrap();
+
+        assert!((q(&table, &s, Action::Draw) - ALPHA * 0.5).abs() < 1e-6);
+    }
 
-            map.insert(i, (Some(action), Some(state)));
-            i += 1;
+    #[test]
+    fn invalid_reward_and_missing_state_are_errors() {
+        let s = state(12, vec![Action::Draw, Action::Stand]);
+        let table = table_with(&[(&s, vec![0.0; Action::ALL.len()])]);
+
+        let nan = table.update(&Transition {
+            state: s.clone(),
+            action: Action::Stand,
+            reward: f32::NAN,
+            next_state: None,
+            next_action: None,
+        });
+        assert!(matches!(nan, Err(BlackjackError::InvalidReward(_))));
+
+        let missing = table.update(&Transition {
+            state: s,
+            action: Action::Draw,
+            reward: 0.0,
+            next_state: Some(state(15, vec![Action::Stand])),
+            next_action: None,
+        });
+        assert!(matches!(missing, Err(BlackjackError::MissingState(_))));
+    }
 
-            match game_state.play(action) {
-                Ok(new_game_state) => {
-                    *game_state = new_game_state;
-                }
-                Err(e) => {
-                    println!("Erreur : {}", e);
-                }
-            }
+    #[test]
+    fn episode_rewards_only_the_last_decision() {
+        let s12 = state(12, vec![Action::Draw, Action::Stand]);
+        let s17 = state(17, vec![Action::Stand]);
+        let table = table_with(&[
+            (&s12, vec![0.0; Action::ALL.len()]),
+            (&s17, vec![0.0; Action::ALL.len()]),
+        ]);
+        // hit on 12, stand on 17 and lose
+        let episode = vec![
+            Transition {
+                state: s12.clone(),
+                action: Action::Draw,
+                reward: 0.0,
+                next_state: Some(s17.clone()),
+                next_action: Some(Action::Stand),
+            },
+            Transition {
+                state: s17.clone(),
+                action: Action::Stand,
+                reward: -1.0,
+                next_state: None,
+                next_action: None,
+            },
+        ];
+
+        table.learn(&episode).unwrap();
+
+        assert!((q(&table, &s17, Action::Stand) + ALPHA).abs() < 1e-6);
+        assert!((q(&table, &s12, Action::Draw) + ALPHA * ALPHA).abs() < 1e-6);
+        assert_eq!(q(&table, &s12, Action::Stand), 0.0);
+    }
+
+    // Environment playing a written round whatever the actions
+    struct Scripted {
+        first: State,
+        steps: Vec<(State, f32, bool, Info)>,
+    }
+
+    impl Environment for Scripted {
+        type Observation = State;
+
+        fn reset(&mut self, _seed: Option<u64>) -> Result<State, BlackjackError> {
+            self.steps.reverse();
+            Ok(self.first.clone())
         }
-        let state = State::from(game_state);
-        self.add_state(state.clone());
 
-        map.insert(i, (None, Some(state)));
+        fn step(&mut self, _action: Action) -> Result<(State, f32, bool, Info), BlackjackError> {
+            Ok(self.steps.pop().unwrap())
+        }
 
-        while game_state.croupier_cards.sum() < 17 {
-            let card = game_state.packet.pick().unwrap();
-            game_state.croupier_cards.add_card(card.clone());
-            game_state.discard.add_card(card.clone());
+        fn legal_actions(&self) -> Vec<Action> {
+            vec![Action::Stand]
         }
 
-        let reward = game_state.results(bet);
+        fn observation_space(&self) -> ObservationSpace {
+            BlackjackEnv::new(RuleSet::default(), StateEncoder::Classic).observation_space()
+        }
+    }
 
-        for (index, (action, state)) in &map {
-            match (state, action) {
-                (Some(state), Some(action)) if *index < map.len() - 1 => self.update(
-                    &state,
-                    &map.get(&(index + 1)).unwrap().1.clone().unwrap(),
-                    &action,
-                    &map.get(&(index + 1)).unwrap().0.clone(),
-                    reward,
+    #[test]
+    fn split_hands_end_with_their_own_money() {
+        let pair = state(16, vec![Action::Stand, Action::Split]);
+        let (s11, s18, s20) = (
+            state(11, vec![Action::Stand]),
+            state(18, vec![Action::Stand]),
+            state(20, vec![Action::Stand]),
+        );
+        let info = |hands, hand, current_hand| Info {
+            hands,
+            hand,
+            hand_over: hand != current_hand,
+            current_hand,
+            ..Info::default()
+        };
+        // split 8s, split again, then stand on 11, 18 and 20
+        let mut env = Scripted {
+            first: pair.clone(),
+            steps: vec![
+                (pair.clone(), 0.0, false, info(2, 0, 0)),
+                (s11.clone(), 0.0, false, info(3, 0, 0)),
+                (s18.clone(), 0.0, false, info(3, 0, 1)),
+                (s20.clone(), 0.0, false, info(3, 1, 2)),
+                (
+                    s20.clone(),
+                    1.0,
+                    true,
+                    Info {
+                        hand_over: true,
+                        hand_rewards: vec![-1.0, 1.0, 1.0],
+                        ..info(3, 2, 2)
+                    },
                 ),
-                _ => {}
+            ],
+        };
+        let mut actions = vec![Action::Stand, Action::Stand, Action::Stand];
+        actions.extend([Action::Split, Action::Split]);
+        let table = QTable::new();
+        let (episode, reward) = table
+            .play_episode(&mut env, |_| Ok(actions.pop().unwrap()))
+            .unwrap();
+
+        assert_eq!(reward, 1.0);
+        let rewards: Vec<f32> = episode.iter().map(|t| t.reward).collect();
+        // the first split made the three hands, the second one the first two
+        assert_eq!(rewards, vec![1.0, 0.0, -1.0, 1.0, 1.0]);
+        assert!(episode.iter().all(|t| t.next_state.is_none()));
+        assert_eq!(table.states.len(), 4);
+    }
+
+    #[test]
+    fn visit_learning_rate_averages_the_targets() {
+        let s = state(16, vec![Action::Draw, Action::Stand]);
+        let mut table = table_with(&[(&s, vec![0.0; Action::ALL.len()])]);
+        table.metadata.learning_rate = LearningRate::Visits;
+        for reward in [1.0, -1.0, -1.0, 0.0] {
+            table
+                .update(&Transition {
+                    state: s.clone(),
+                    action: Action::Stand,
+                    reward,
+                    next_state: None,
+                    next_action: None,
+                })
+                .unwrap();
+        }
+        assert!((q(&table, &s, Action::Stand) + 0.25).abs() < 1e-6);
+        assert_eq!(table.state_visits(&s), 4);
+    }
+
+    #[test]
+    fn boltzmann_prefers_the_best_action() {
+        let s = state(16, vec![Action::Draw, Action::Stand]);
+        let mut values = vec![0.0; Action::ALL.len()];
+        values[Action::Stand.into_index()] = -0.5;
+        values[Action::Draw.into_index()] = -0.4;
+        let mut table = QTable::new();
+        table.metadata.exploration = Exploration::Boltzmann {
+            start: 1.0,
+            end: 0.01,
+        };
+        let probabilities = |episode| {
+            Behaviour {
+                table: &table,
+                episode,
+                total: 100,
             }
+            .probabilities(&s, &values)
+        };
+        let hot = probabilities(0);
+        let cold = probabilities(100);
+        assert!((hot.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+        assert!((cold.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+        // Draw comes first in the legal actions
+        assert!(hot[0] > 0.5 && hot[0] < 0.6);
+        assert!(cold[0] > 0.99);
+    }
+
+    #[test]
+    fn training_games_keep_values_in_the_payout_range() {
+        let table = QTable::with_encoder(RuleSet::default(), StateEncoder::Classic);
+        let mut env = table.environment(GameState::seeded(RuleSet::default(), 3));
+        let mut rng = StdRng::seed_from_u64(3);
+        for _ in 0..200 {
+            let reward = table.trainnig_q(&mut env, 1.0, &mut rng).unwrap();
+            assert!(reward.is_finite());
         }
-        reward
+        // With gamma = 1 no value can go further than the largest payout of a round
+        for entry in table.states.iter() {
+            assert!(entry.value().iter().all(|v| v.abs() <= 8.0));
+        }
+    }
+
+    #[test]
+    fn saved_table_loads_back_and_bad_values_are_refused() {
+        let table = QTable::with_encoder(RuleSet::default(), StateEncoder::Classic);
+        let mut env = table.environment(GameState::seeded(RuleSet::default(), 4));
+        let mut rng = StdRng::seed_from_u64(4);
+        for _ in 0..100 {
+            table.trainnig_q(&mut env, 1.0, &mut rng).unwrap();
+        }
+        let path = std::env::temp_dir().join(format!(
+            "black_jack_ia_{}_load_test.json",
+            std::process::id()
+        ));
+        let path = path.to_str().unwrap();
+        table.save(path).unwrap();
+
+        let loaded = QTable::load(path).unwrap();
+        assert_eq!(loaded.games(), 100);
+        assert_eq!(loaded.metadata.encoder, StateEncoder::Classic);
+        assert_eq!(loaded.states.len(), table.states.len());
+        for entry in table.states.iter() {
+            assert_eq!(*loaded.states.get(entry.key()).unwrap(), *entry.value());
+            assert_eq!(
+                loaded.state_visits(entry.key()),
+                table.state_visits(entry.key())
+            );
+        }
+
+        // a state with one value too few, then one visit count too many
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+        for (field, change) in [("actions", -1), ("visits", 1)] {
+            let mut json = json.clone();
+            let values = json["states"][0][field].as_array_mut().unwrap();
+            if change < 0 {
+                values.pop();
+            } else {
+                values.push(values[0].clone());
+            }
+            std::fs::write(path, json.to_string()).unwrap();
+            assert!(matches!(
+                QTable::load(path),
+                Err(BlackjackError::InvalidTable(_))
+            ));
+        }
+
+        let run = RunState {
+            episodes: 100,
+            checkpoint: 1,
+            threads: Vec::new(),
+            learner: Vec::new(),
+            learner_visits: Vec::new(),
+        };
+        table.save_checkpoint(path, run).unwrap();
+        assert!(matches!(
+            QTable::load_checkpoint(path),
+            Err(BlackjackError::InvalidTable(_))
+        ));
+        std::fs::remove_file(path).unwrap();
     }
 }